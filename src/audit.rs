@@ -0,0 +1,133 @@
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+
+use crate::history::Entity;
+use crate::inventory::{Audit, Compartment, Container, EntityKind, IdType, Inventory, Item, LogEntry, Tag};
+
+/// The parts of an entity the history and the audit need
+pub trait Audited : Clone + PartialEq
+{
+    const KIND: EntityKind;
+    fn get_id(&self) -> IdType;
+    fn get_name(&self) -> &str;
+    fn get_audit_mut(&mut self) -> &mut Audit;
+    /// The list of the inventory holding the entities of this kind
+    fn get_list(inventory : &Inventory) -> &Vec<Self>;
+    fn get_list_mut(inventory : &mut Inventory) -> &mut Vec<Self>;
+    fn into_entity(self) -> Entity;
+}
+
+impl Audited for Compartment
+{
+    const KIND: EntityKind = EntityKind::Compartment;
+    fn get_id(&self) -> IdType {return self.id;}
+    fn get_name(&self) -> &str {return &self.name;}
+    fn get_audit_mut(&mut self) -> &mut Audit {return &mut self.audit;}
+    fn get_list(inventory : &Inventory) -> &Vec<Self> {return &inventory.compartments;}
+    fn get_list_mut(inventory : &mut Inventory) -> &mut Vec<Self> {return &mut inventory.compartments;}
+    fn into_entity(self) -> Entity {return Entity::Compartment(self);}
+}
+
+impl Audited for Container
+{
+    const KIND: EntityKind = EntityKind::Container;
+    fn get_id(&self) -> IdType {return self.id;}
+    fn get_name(&self) -> &str {return &self.name;}
+    fn get_audit_mut(&mut self) -> &mut Audit {return &mut self.audit;}
+    fn get_list(inventory : &Inventory) -> &Vec<Self> {return &inventory.containers;}
+    fn get_list_mut(inventory : &mut Inventory) -> &mut Vec<Self> {return &mut inventory.containers;}
+    fn into_entity(self) -> Entity {return Entity::Container(self);}
+}
+
+impl Audited for Item
+{
+    const KIND: EntityKind = EntityKind::Item;
+    fn get_id(&self) -> IdType {return self.id;}
+    fn get_name(&self) -> &str {return &self.name;}
+    fn get_audit_mut(&mut self) -> &mut Audit {return &mut self.audit;}
+    fn get_list(inventory : &Inventory) -> &Vec<Self> {return &inventory.items;}
+    fn get_list_mut(inventory : &mut Inventory) -> &mut Vec<Self> {return &mut inventory.items;}
+    fn into_entity(self) -> Entity {return Entity::Item(self);}
+}
+
+impl Audited for Tag
+{
+    const KIND: EntityKind = EntityKind::Tag;
+    fn get_id(&self) -> IdType {return self.id;}
+    fn get_name(&self) -> &str {return &self.name;}
+    fn get_audit_mut(&mut self) -> &mut Audit {return &mut self.audit;}
+    fn get_list(inventory : &Inventory) -> &Vec<Self> {return &inventory.tags;}
+    fn get_list_mut(inventory : &mut Inventory) -> &mut Vec<Self> {return &mut inventory.tags;}
+    fn into_entity(self) -> Entity {return Entity::Tag(self);}
+}
+
+/// Who changes the inventory now. The time is RFC 3339 in UTC, e.g. "2024-05-01T12:00:00Z",
+/// so stamps made in different timezones are unambiguous and sort by time.
+pub struct Actor
+{
+    time : String,
+    user : String
+}
+
+impl Actor
+{
+    pub fn now() -> Actor
+    {
+        return Actor{time : Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), user : whoami::username()};
+    }
+}
+
+/// The stamp in the local time of this machine, as it is shown in the terminal. Stamps made
+/// before they were stored in UTC are shown as they are.
+pub fn local_time(stamp : &str) -> String
+{
+    return match DateTime::parse_from_rfc3339(stamp)
+    {
+        Ok(time) => time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+        Err(_) => stamp.to_string()
+    };
+}
+
+/// Stamps the entity with the time and the user, a created entity gets its creation stamp as well
+pub fn stamp(audit : &mut Audit, actor : &Actor, created : bool)
+{
+    if created
+    {
+        audit.created_at = actor.time.clone();
+        audit.created_by = actor.user.clone();
+    }
+    audit.modified_at = actor.time.clone();
+    audit.modified_by = actor.user.clone();
+}
+
+pub fn log_change(log : &mut Vec<LogEntry>, actor : &Actor, kind : EntityKind, id : IdType, name : &str, action : &str, command : &str)
+{
+    log.push(LogEntry
+    {
+        time    : actor.time.clone(),
+        user    : actor.user.clone(),
+        kind,
+        id,
+        name    : name.to_string(),
+        action  : action.to_string(),
+        command : command.to_string()
+    });
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn stamps_in_utc()
+    {
+        let time = Actor::now().time;
+        assert!(time.ends_with('Z'), "{}", time);
+        assert!(DateTime::parse_from_rfc3339(&time).is_ok());
+
+        let shown = local_time("2024-05-01T12:00:00Z");
+        assert_eq!(shown, DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string());
+        assert_eq!(local_time("2024-05-01 14:00:00"), "2024-05-01 14:00:00");
+        assert_eq!(local_time(""), "");
+    }
+}
@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::env;
+use std::fs;
+
+use chrono::{Local, NaiveDateTime};
+
+use crate::inventory::get_file_location;
+
+pub static BACKUP_DIR: &str = "backups";
+pub static DEFAULT_RETENTION: usize = 10;
+/// Scripts save many times a second, so the names go down to the microsecond. Backups made
+/// before had whole seconds only, "%.f" parses both.
+static TIME_FORMAT: &str = "%Y%m%d-%H%M%S%.6f";
+static PARSE_FORMAT: &str = "%Y%m%d-%H%M%S%.f";
+
+pub struct Backup
+{
+    pub path : PathBuf,
+    pub time : NaiveDateTime,
+    /// orders backups with the same time, see create_backup
+    seq      : u32
+}
+
+/// Parses "<timestamp>" or "<timestamp>-<seq>" from a backup name
+fn parse_stamp(stamp : &str) -> Option<(NaiveDateTime, u32)>
+{
+    if let Ok(time) = NaiveDateTime::parse_from_str(stamp, PARSE_FORMAT) {return Some((time, 0));}
+
+    let (time, seq) = stamp.rsplit_once('-')?;
+    return Some((NaiveDateTime::parse_from_str(time, PARSE_FORMAT).ok()?, seq.parse().ok()?));
+}
+
+/// Number of backups kept per inventory file, configured with INVI_BACKUPS (0 disables the backups)
+pub fn get_retention() -> usize
+{
+    return match env::var("INVI_BACKUPS")
+    {
+        Ok(value) => value.trim().parse().unwrap_or(DEFAULT_RETENTION),
+        Err(_) => DEFAULT_RETENTION
+    };
+}
+
+fn get_file_stem(file_name : &str) -> String
+{
+    return Path::new(file_name).file_stem().map_or(String::from("invi"), |s| s.to_string_lossy().to_string());
+}
+
+fn get_extension(file_name : &str) -> String
+{
+    return Path::new(file_name).extension().map_or(String::from("json"), |s| s.to_string_lossy().to_string());
+}
+
+/// Identifies the inventory file by its canonical path, files with the same name in different
+/// directories share the backup directory. FNV-1a is used as it does not change between builds.
+fn get_path_hash(file_name : &str) -> String
+{
+    let path = match fs::canonicalize(file_name)
+    {
+        Ok(path) => path,
+        Err(_) => env::current_dir().map(|dir| dir.join(file_name)).unwrap_or_else(|_| PathBuf::from(file_name))
+    };
+
+    let mut hash : u64 = 0xcbf29ce484222325;
+    for byte in path.to_string_lossy().bytes()
+    {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    return format!("{:016x}", hash);
+}
+
+/// The start of the backup names of the file: "<stem>-<path hash>-"
+fn get_prefix(file_name : &str) -> String
+{
+    return format!("{}-{}-", get_file_stem(file_name), get_path_hash(file_name));
+}
+
+/// Copies the inventory file into the backup directory as "<stem>-<path hash>-<timestamp>.<extension>"
+/// and removes the oldest backups of that file exceeding the retention. An existing backup is never
+/// overwritten, if the name is taken a sequence number is added: "<timestamp>-<seq>".
+pub fn create_backup(file_name : &str) -> Result<(), Box<dyn Error>>
+{
+    let retention = get_retention();
+    if retention == 0 {return Ok(());}
+
+    let dir = get_file_location(BACKUP_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let stamp = Local::now().format(TIME_FORMAT).to_string();
+    let (prefix, extension) = (get_prefix(file_name), get_extension(file_name));
+
+    let mut target = Path::new(&dir).join(format!("{}{}.{}", prefix, stamp, extension));
+    let mut seq = 0;
+    while target.exists()
+    {
+        seq += 1;
+        target = Path::new(&dir).join(format!("{}{}-{}.{}", prefix, stamp, seq, extension));
+    }
+    fs::copy(file_name, target)?;
+
+    for old in list_backups(file_name)?.iter().skip(retention)
+    {
+        fs::remove_file(&old.path)?;
+    }
+
+    return Ok(());
+}
+
+/// All backups of the inventory file, the newest first
+pub fn list_backups(file_name : &str) -> Result<Vec<Backup>, Box<dyn Error>>
+{
+    let dir = get_file_location(BACKUP_DIR);
+    let prefix = get_prefix(file_name);
+    let suffix = format!(".{}", get_extension(file_name));
+    let mut backups = Vec::new();
+
+    if !Path::new(&dir).exists() {return Ok(backups);}
+
+    for entry in fs::read_dir(&dir)?
+    {
+        let path = entry?.path();
+        let name = match path.file_name() {Some(name) => name.to_string_lossy().to_string(), None => continue};
+
+        if name.len() < prefix.len() + suffix.len() || !name.starts_with(&prefix) || !name.ends_with(&suffix) {continue;}
+
+        // the name of another file could share the prefix, so only accept a valid timestamp
+        let stamp = &name[prefix.len()..name.len() - suffix.len()];
+        if let Some((time, seq)) = parse_stamp(stamp)
+        {
+            backups.push(Backup{path, time, seq});
+        }
+    }
+
+    backups.sort_by_key(|b| std::cmp::Reverse((b.time, b.seq)));
+    return Ok(backups);
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parses_the_backup_stamps()
+    {
+        let time = |stamp| NaiveDateTime::parse_from_str(stamp, "%Y-%m-%d %H:%M:%S%.f").unwrap();
+
+        assert_eq!(parse_stamp("20240102-030405"), Some((time("2024-01-02 03:04:05"), 0)));
+        assert_eq!(parse_stamp("20240102-030405.000123"), Some((time("2024-01-02 03:04:05.000123"), 0)));
+        assert_eq!(parse_stamp("20240102-030405.000123-2"), Some((time("2024-01-02 03:04:05.000123"), 2)));
+        assert_eq!(parse_stamp("20240102-030405-extra"), None);
+        assert_eq!(parse_stamp("notes"), None);
+    }
+}
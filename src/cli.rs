@@ -0,0 +1,423 @@
+use std::error::Error;
+use std::mem;
+
+use crate::error::GenericError;
+use crate::gui::AppContext;
+use crate::inventory::EntityKind;
+use crate::output::Format;
+use crate::script::{self, ScriptOptions};
+
+/// The subcommands of the command line, e.g. invi add item "M3 screw" --container 0x2
+static COMMANDS: [&str; 16] =
+[
+    "add", "rm", "mv", "ls", "find", "show", "edit", "take", "put", "tag", "untag", "restock", "log", "fsck", "recover", "help"
+];
+
+/// The subcommands which can write their result as JSON
+static QUERIES: [&str; 4] = ["ls", "find", "show", "restock"];
+
+/// The hints of the error messages which name : commands, with what to do on the command line instead
+static HINTS: [(&str, &str); 9] =
+[
+    ("remove it with :dcont!",                                "remove it with rm container <id> --force"),
+    ("remove it with :dcomp!",                                "remove it with rm compartment <id> --force"),
+    ("replay it with :recover or discard it with :recover!",  "replay it with recover or discard it with recover --discard"),
+    ("save the changes to another file with :w <file>",       "try again when it was closed"),
+    ("reload it with :e! or overwrite it with :w!",           "run the command again"),
+    ("run :fsck! to repair",                                  "run fsck --repair to repair"),
+    ("repair it with :fsck!",                                 "repair it with fsck --repair"),
+    ("use :/<str>",                                           "use find <str>"),
+    ("use :",                                                 "use ")
+];
+
+pub fn is_command(arg : &str) -> bool
+{
+    return COMMANDS.contains(&arg);
+}
+
+pub fn print_commands()
+{
+    println!("Commands:");
+    println!("    {:46}{}","add item <name> --container <id>","adds an item, [--quantity <n>] [--unit <unit>]");
+    println!("    {:46}{}","add container <name> --compartment <id>","adds a container, [--tag <id>]...");
+    println!("    {:46}{}","add compartment <name> | add tag <name>","adds a compartment or a tag");
+    println!("    {:46}{}","rm <kind> <id> [--force]","deletes an entity, --force deletes its content too");
+    println!("    {:46}{}","mv item <id> <container>","moves an item");
+    println!("    {:46}{}","mv items <from> <to>","moves all items of a container");
+    println!("    {:46}{}","mv container <id> <compartment>","moves a container");
+    println!("    {:46}{}","ls [kind] | find <str>","lists everything or searches like :/");
+    println!("    {:46}{}","show [kind] <id>","shows everything about the entities with the id");
+    println!("    {:46}{}","ls, find, show, restock [--json | --ndjson]","write the result as one JSON document or one record per line");
+    println!("    {:46}{}","edit, take, put, tag, untag, restock, log","take the same arguments as their : command");
+    println!("    {:46}{}","fsck [--repair]","checks the inventory, the exit status is 1 if it has problems, --repair repairs them");
+    println!("    {:46}{}","recover [--discard]","replays and writes the recovery journal of a session which was not closed");
+    println!("The kinds are comp or compartment, cont or container, item and tag, the plurals work as well.");
+    println!("Ids are hex numbers with an optional 0x prefix. The exit status is 0 on success, 1 if the command");
+    println!("failed and 2 for invalid arguments. Changes are written to the file right away.");
+}
+
+fn usage_error(msg : &str) -> Box<dyn Error>
+{
+    return Box::new(GenericError::new(format!("{}, see invi help", msg)));
+}
+
+/// Replaces the hints for the TUI in the messages with the subcommands to use
+fn to_command_line(output : &str) -> String
+{
+    return HINTS.iter().fold(output.to_string(), |output, (tui, cli)| output.replace(tui, cli));
+}
+
+/// Removes --json and --ndjson from the arguments, only the queries take them
+fn split_format(args : &[String]) -> Result<(Vec<String>, Format), Box<dyn Error>>
+{
+    let mut format = Format::Text;
+    let mut rest = Vec::new();
+
+    for arg in args
+    {
+        match arg.as_str()
+        {
+            "--json"   => format = Format::Json,
+            "--ndjson" => format = Format::Ndjson,
+            _ => rest.push(arg.clone())
+        }
+    }
+
+    if format != Format::Text && !QUERIES.contains(&rest[0].as_str())
+        {return Err(usage_error(&format!("{} has no JSON output, only {} have", rest[0], QUERIES.join(", "))));}
+
+    return Ok((rest, format));
+}
+
+/// Quotes every argument, so names with spaces stay one argument for get_arguments
+fn join_quoted(command : &str, args : &[String]) -> String
+{
+    let mut line = command.to_string();
+    for arg in args {line.push_str(&format!(" \"{}\"", arg));}
+    return line;
+}
+
+/// The given flags with their values, the value of a flag without one is empty
+type Flags = Vec<(String, String)>;
+
+/// Splits "--flag value" pairs and the flags without a value from the positional arguments
+fn split_flags(args : &[String], with_value : &[&str], switches : &[&str]) -> Result<(Vec<String>, Flags), Box<dyn Error>>
+{
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next()
+    {
+        if switches.contains(&arg.as_str())
+        {
+            flags.push((arg.clone(), String::new()));
+        }
+        else if with_value.contains(&arg.as_str())
+        {
+            match iter.next()
+            {
+                Some(value) => flags.push((arg.clone(), value.clone())),
+                None => return Err(usage_error(&format!("{} needs a value", arg)))
+            }
+        }
+        else if arg.starts_with("--")
+        {
+            return Err(usage_error(&format!("Unknown option {}", arg)));
+        }
+        else
+        {
+            positional.push(arg.clone());
+        }
+    }
+
+    return Ok((positional, flags));
+}
+
+fn get_flag(flags : &[(String, String)], name : &str) -> Option<String>
+{
+    return flags.iter().find(|(flag, _)| flag == name).map(|(_, value)| value.clone());
+}
+
+fn get_name(positional : &[String]) -> Result<String, Box<dyn Error>>
+{
+    if positional.len() != 1 {return Err(usage_error("Give exactly one name, quote names with spaces"));}
+    return Ok(positional[0].clone());
+}
+
+fn translate_add(args : &[String]) -> Result<String, Box<dyn Error>>
+{
+    let kind = match args.first() {Some(kind) => EntityKind::parse(kind)?, None => return Err(usage_error("add needs a kind"))};
+    let (positional, flags) = split_flags(&args[1..], &["--container", "--compartment", "--quantity", "--unit", "--tag"], &[])?;
+    let name = get_name(&positional)?;
+
+    return match kind
+    {
+        EntityKind::Item =>
+        {
+            let container = match get_flag(&flags, "--container") {Some(id) => id, None => return Err(usage_error("add item needs --container <id>"))};
+            let mut line = vec![name, container];
+
+            let unit = get_flag(&flags, "--unit");
+            if let Some(quantity) = get_flag(&flags, "--quantity") {line.push(quantity);}
+            else if unit.is_some() {line.push("1".to_string());}
+            if let Some(unit) = unit {line.push(unit);}
+
+            Ok(join_quoted(":aitem", &line))
+        }
+        EntityKind::Container =>
+        {
+            let compartment = match get_flag(&flags, "--compartment") {Some(id) => id, None => return Err(usage_error("add container needs --compartment <id>"))};
+            let mut line = vec![name, compartment];
+            line.extend(flags.iter().filter(|(flag, _)| flag == "--tag").map(|(_, id)| id.clone()));
+            Ok(join_quoted(":acont", &line))
+        }
+        EntityKind::Compartment if flags.is_empty() => Ok(join_quoted(":acomp", &[name])),
+        EntityKind::Tag if flags.is_empty() => Ok(join_quoted(":atag", &[name])),
+        _ => Err(usage_error(&format!("add {} takes no options", kind)))
+    };
+}
+
+fn translate_rm(args : &[String]) -> Result<String, Box<dyn Error>>
+{
+    let (positional, flags) = split_flags(args, &[], &["--force"])?;
+    if positional.len() != 2 {return Err(usage_error("Use rm <kind> <id>"));}
+
+    let force = if get_flag(&flags, "--force").is_some() {"!"} else {""};
+    let command = match EntityKind::parse(&positional[0])?
+    {
+        EntityKind::Item        => ":ditem".to_string(),
+        EntityKind::Tag         => ":dtag".to_string(),
+        EntityKind::Container   => format!(":dcont{}", force),
+        EntityKind::Compartment => format!(":dcomp{}", force)
+    };
+
+    return Ok(join_quoted(&command, &positional[1..]));
+}
+
+fn translate_mv(args : &[String]) -> Result<String, Box<dyn Error>>
+{
+    if args.len() != 3 {return Err(usage_error("Use mv <item|items|container> <id> <target>"));}
+
+    let command = match args[0].as_str()
+    {
+        "items" => ":mitems",
+        kind => match EntityKind::parse(kind)?
+        {
+            EntityKind::Item      => ":mitem",
+            EntityKind::Container => ":mcont",
+            _ => return Err(usage_error(&format!("A {} can not be moved", kind)))
+        }
+    };
+
+    return Ok(join_quoted(command, &args[1..]));
+}
+
+/// Turns the subcommand into the : command the TUI would execute
+fn translate(args : &[String]) -> Result<String, Box<dyn Error>>
+{
+    let rest = &args[1..];
+
+    return match args[0].as_str()
+    {
+        "add"  => translate_add(rest),
+        "rm"   => translate_rm(rest),
+        "mv"   => translate_mv(rest),
+        "find" if !rest.is_empty() => Ok(format!(":/{}", rest.join(" "))),
+        "find" => Err(usage_error("find needs something to search for")),
+        "fsck" => match split_flags(rest, &[], &["--repair"])?
+        {
+            (positional, _) if !positional.is_empty() => Err(usage_error("fsck takes no arguments")),
+            (_, flags) if flags.is_empty() => Ok(":fsck".to_string()),
+            _ => Ok(":fsck!".to_string())
+        },
+        "recover" => match split_flags(rest, &[], &["--discard"])?
+        {
+            (positional, _) if !positional.is_empty() => Err(usage_error("recover takes no arguments")),
+            (_, flags) if flags.is_empty() => Ok(":recover".to_string()),
+            _ => Ok(":recover!".to_string())
+        },
+        command => Ok(join_quoted(&format!(":{}", command), rest))
+    };
+}
+
+/// Runs one subcommand on the inventory file and writes the changes back,
+/// the output goes to stdout and the errors to stderr. Returns the exit status.
+pub fn run(file_name : &str, args : &[String]) -> i32
+{
+    if args[0] == "help" {print_commands(); return 0;}
+
+    let (input, format) = match split_format(args).and_then(|(args, format)| Ok((translate(&args)?, format)))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => {eprintln!("{}", e); return 2;}
+    };
+
+    let mut context = match AppContext::new(file_name)
+    {
+        Ok(context) => context,
+        Err(e) => {eprintln!("Error: Loading {} failed: {}", file_name, e); return 1;}
+    };
+
+    // notices of the load, e.g. a read-only file
+    eprint!("{}", mem::take(context.get_terminal_ref()));
+    context.output = format;
+
+    crate::dispatch_input(&input, &mut context);
+    let output = mem::take(context.get_terminal_ref());
+
+    if context.errors != 0
+    {
+        eprint!("{}", to_command_line(&output));
+        return if context.usage_errors != 0 {2} else {1};
+    }
+
+    print!("{}", output);
+
+    if context.invi_dirty
+    {
+        if let Err(e) = crate::write_back_file(&mut context, false)
+        {
+            eprintln!("Error while saving file: {}", e);
+            return 1;
+        }
+    }
+
+    return 0;
+}
+
+/// Runs a script on the inventory file and writes the changes back unless it was a dry run or it
+/// stopped at a failed command, the exit status is 1 if a command of the script failed
+pub fn run_script(file_name : &str, script_file : &str, options : ScriptOptions) -> i32
+{
+    let mut context = match AppContext::new(file_name)
+    {
+        Ok(context) => context,
+        Err(e) => {eprintln!("Error: Loading {} failed: {}", file_name, e); return 1;}
+    };
+
+    eprint!("{}", mem::take(context.get_terminal_ref()));
+
+    // the output of a failed command goes to stderr, like the one of a failed subcommand
+    let mut on_line = |context : &mut AppContext, succeeded : bool|
+    {
+        let output = mem::take(context.get_terminal_ref());
+        if succeeded {print!("{}", output);} else {eprint!("{}", output);}
+    };
+
+    let summary = match script::source_with(&mut context, script_file, options, &mut on_line)
+    {
+        Ok(summary) => summary,
+        Err(e) => {eprintln!("Error while running the script {}: {}", script_file, e); return 1;}
+    };
+
+    print!("{}", mem::take(context.get_terminal_ref()));
+    println!("{}", summary);
+
+    // a script which stopped halfway is not written, with --keep-going the commands which succeeded are
+    if summary.failed != 0 && !options.keep_going
+    {
+        if context.invi_dirty
+        {
+            // the discarded changes must not be offered for recovery either
+            if let Err(e) = crate::clear_journal(&context) {eprintln!("Error while removing the recovery journal: {}", e);}
+
+            if summary.written
+                {eprintln!("The script failed, the changes after its last :w were not written to {}", context.file_name);}
+            else
+                {eprintln!("Nothing was written to {} because the script failed", file_name);}
+        }
+        return 1;
+    }
+
+    if context.invi_dirty && !options.dry_run
+    {
+        if let Err(e) = crate::write_back_file(&mut context, false)
+        {
+            eprintln!("Error while saving file: {}", e);
+            return 1;
+        }
+    }
+
+    return if summary.failed == 0 {0} else {1};
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::fs;
+
+    use super::*;
+    use crate::tests::temp_file;
+
+    fn args(line : &[&str]) -> Vec<String>
+    {
+        return line.iter().map(|arg| arg.to_string()).collect();
+    }
+
+    #[test]
+    fn translates_the_subcommands()
+    {
+        let translated = |line : &[&str]| translate(&args(line)).map_err(|e| e.to_string());
+
+        assert_eq!(translated(&["add", "item", "M3 screw", "--container", "0x2", "--unit", "pcs"]).unwrap(), ":aitem \"M3 screw\" \"0x2\" \"1\" \"pcs\"");
+        assert_eq!(translated(&["add", "container", "Box", "--compartment", "1", "--tag", "0", "--tag", "2"]).unwrap(), ":acont \"Box\" \"1\" \"0\" \"2\"");
+        assert_eq!(translated(&["rm", "compartments", "3", "--force"]).unwrap(), ":dcomp! \"3\"");
+        assert_eq!(translated(&["mv", "items", "1", "2"]).unwrap(), ":mitems \"1\" \"2\"");
+        assert_eq!(translated(&["find", "m3", "screw"]).unwrap(), ":/m3 screw");
+        assert_eq!(translated(&["fsck", "--repair"]).unwrap(), ":fsck!");
+        assert_eq!(translated(&["recover", "--discard"]).unwrap(), ":recover!");
+
+        assert!(translated(&["add", "item", "Nut"]).unwrap_err().contains("--container"));
+        assert!(translated(&["add", "tag", "ESD", "--unit", "pcs"]).is_err());
+        assert!(translated(&["rm", "item", "1", "--all"]).unwrap_err().contains("Unknown option"));
+        assert!(translated(&["mv", "tag", "1", "2"]).is_err());
+        assert!(translated(&["fsck", "now"]).is_err());
+    }
+
+    #[test]
+    fn only_the_queries_write_json()
+    {
+        let (rest, format) = split_format(&args(&["ls", "items", "--ndjson"])).unwrap();
+        assert_eq!(rest, args(&["ls", "items"]));
+        assert_eq!(format, Format::Ndjson);
+
+        assert!(split_format(&args(&["add", "tag", "ESD", "--json"])).is_err());
+    }
+
+    #[test]
+    fn shows_the_subcommands_in_the_hints()
+    {
+        assert_eq!(to_command_line("The container with the id: 0 is not empty, remove it with :dcont! to delete its items as well"),
+                   "The container with the id: 0 is not empty, remove it with rm container <id> --force to delete its items as well");
+        assert_eq!(to_command_line("Error invalid number of arguments, use :show [kind] <id>"), "Error invalid number of arguments, use show [kind] <id>");
+    }
+
+    #[test]
+    fn exits_with_the_status_of_the_command()
+    {
+        let (dir, file_name) = temp_file("cli");
+        let status = |line : &[&str]| run(&file_name, &args(line));
+
+        assert_eq!(status(&["ls", "items"]), 0);
+        assert_eq!(status(&["show", "item", "1", "--json"]), 0);
+        assert_eq!(status(&["fsck"]), 0);
+        assert_eq!(status(&["take", "1", "1"]), 0);
+
+        // failed commands
+        assert_eq!(status(&["rm", "container", "0"]), 1);
+        assert_eq!(status(&["take", "1", "5"]), 1);
+
+        // invalid arguments
+        assert_eq!(status(&["rm", "container"]), 2);
+        assert_eq!(status(&["find", ""]), 2);
+        assert_eq!(status(&["ls", "boxes"]), 2);
+        assert_eq!(status(&["add", "tag", "ESD", "--json"]), 2);
+
+        let inventory = crate::storage::load_file(&file_name).unwrap();
+        assert_eq!(inventory.items[1].quantity, 0);
+        assert_eq!(inventory.containers.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -34,4 +34,28 @@ impl error::Error for GenericError
     {
         None
     }
-}
\ No newline at end of file
+}
+/// The arguments of a command are wrong, the command line exits with 2 for it
+#[derive(Debug, Clone)]
+pub struct UsageError
+{
+    msg : String
+}
+
+impl UsageError
+{
+    pub fn new(err_msg : String) -> UsageError
+    {
+        return UsageError{msg : err_msg};
+    }
+}
+
+impl fmt::Display for UsageError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl error::Error for UsageError {}
@@ -1,14 +1,18 @@
 
 use std::io::{self};
 use std::error::Error;
-use std::cmp::{max};
 
 use tui::{Frame, Terminal};
 use tui::backend::Backend;
 use tui::layout::{Constraint, Direction, Layout, Rect, Corner};
-use tui::style::{Color, Style};
+use tui::style::{Color, Modifier, Style};
 use tui::widgets::{ Block, Borders, Paragraph, Text, Widget,List};
-use crate::inventory::{Inventory,load_inventory_from_home};
+use crate::inventory::{Inventory,search,match_range};
+use crate::storage::{Storage,open_storage};
+use crate::lock::{self,FileLock,FileStamp};
+use crate::journal;
+use crate::history::History;
+use crate::output::Format;
 
 
 pub enum InviLayout
@@ -18,7 +22,10 @@ pub enum InviLayout
     Overview
 }
 
-//eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee
+/// Number of tick events between two checks of the open file for external changes
+const WATCH_TICKS: u32 = 10;
+
+//eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee
 pub struct AppContext
 {
     pub txt_input  : String,
@@ -30,35 +37,188 @@ pub struct AppContext
     pub invi_dirty : bool,
     pub scroll_back: usize,
     pub scroll_items : Vec<String>,
+    pub search_key : String,
+    pub file_name  : String,
+    pub storage    : Box<dyn Storage>,
+    pub read_only  : bool,
+    /// the file as it was on disk after the last load or save
+    pub file_stamp : Option<FileStamp>,
+    lock           : Option<FileLock>,
+    /// the external change of the file the user was already told about
+    seen_stamp     : Option<FileStamp>,
+    ticks          : u32,
+    /// None if autosave is off
+    pub autosave   : Option<Duration>,
+    pub last_save  : Instant,
+    /// there is a recovery journal of the open file which was neither replayed nor discarded
+    pub recovery_pending : bool,
+    pub history    : History,
+    /// in normal mode keys are commands (u undoes, Ctrl-R redoes) instead of being typed
+    pub normal_mode : bool,
+    /// number of failed commands, the command line turns it into the exit code
+    pub errors     : u32,
+    /// number of commands which failed because of invalid arguments, they are part of errors
+    pub usage_errors : u32,
+    /// how the queries write their results, the command line can ask for JSON
+    pub output     : Format,
+    /// a script is run on a copy of the inventory, nothing is journaled
+    pub dry_run    : bool,
     
     term_txt   : String,
 }
 
 impl AppContext
 {
-    pub fn new() -> Result<(AppContext), Box<dyn Error>> 
+    pub fn new(file_name : &str) -> Result<AppContext, Box<dyn Error>> 
     {
-
         let sc_items = vec![":acont ".to_string(),":acomp ".to_string(), ":aitem ".to_string(), ":atag ".to_string()];
         let sc_items_len = sc_items.len();
-        let context = AppContext
+        let mut storage = open_storage(file_name)?;
+        let mut context = AppContext
         {
             txt_input    : String::new(),
             cursor_pos   : 0,
             layout       : InviLayout::Overview,
-            inventory    : load_inventory_from_home()?,
+            inventory    : storage.load()?,
             invi_dirty   : false,
             size_term    : Rect::new(0,0,0,0),
             gui_dirty    : true, 
             scroll_items : sc_items,
             scroll_back  : sc_items_len,
+            search_key   : String::new(),
+            file_name    : file_name.to_string(),
+            storage,
+            read_only    : false,
+            file_stamp   : None,
+            lock         : None,
+            seen_stamp   : None,
+            ticks        : 0,
+            autosave     : journal::get_autosave_interval(),
+            last_save    : Instant::now(),
+            recovery_pending : false,
+            history      : History::new(),
+            normal_mode  : false,
+            errors       : 0,
+            usage_errors : 0,
+            output       : Format::Text,
+            dry_run      : false,
 
             term_txt : String::new(),
         };
 
+        context.start_session(file_name)?;
+
         return Ok(context);
     }
 
+    /// Replaces the inventory with the one stored in the file, unwritten changes are lost.
+    /// If another invi session has the file locked it is opened read-only.
+    pub fn open_file(&mut self, file_name : &str) -> Result<(), Box<dyn Error>>
+    {
+        let mut storage = open_storage(file_name)?;
+        self.inventory  = storage.load()?;
+        self.storage    = storage;
+        self.file_name  = file_name.to_string();
+        self.invi_dirty = false;
+        self.gui_dirty  = true;
+        self.history    = History::new();
+
+        // drop our own lock first, reopening the same file would block on it otherwise
+        self.lock       = None;
+        return self.start_session(file_name);
+    }
+
+    /// Locks the loaded file and looks for a recovery journal
+    fn start_session(&mut self, file_name : &str) -> Result<(), Box<dyn Error>>
+    {
+        self.lock       = lock::try_lock(file_name)?;
+        self.read_only  = self.lock.is_none();
+        self.file_stamp = lock::get_stamp(file_name)?;
+        self.seen_stamp = None;
+        self.last_save  = Instant::now();
+        self.recovery_pending = false;
+
+        if self.read_only
+        {
+            self.write_to_terminal(&format!("{} is locked by another invi session, it was opened read-only\n", file_name));
+            return Ok(());
+        }
+
+        let pending = journal::read(file_name)?.len();
+        if pending == 0 {return Ok(());}
+
+        // the file was written after the journal, it may contain its changes already
+        if !journal::is_newer(file_name)
+        {
+            let old = journal::set_aside(file_name)?;
+            self.write_to_terminal(&format!("Found a recovery journal with {} change(s) which is older than {}, it was moved to {}\n", pending, file_name, old));
+            return Ok(());
+        }
+
+        self.recovery_pending = true;
+        self.write_to_terminal(&format!("Found a recovery journal with {} unwritten change(s), invi was not closed properly\n", pending));
+        self.write_to_terminal("    replay it with :recover or discard it with :recover!, on the command line with recover [--discard]\n");
+
+        return Ok(());
+    }
+
+    /// Switches to a new file after the inventory was saved as that file
+    pub fn saved_as(&mut self, file_name : &str, storage : Box<dyn Storage>, lock : FileLock) -> Result<(), Box<dyn Error>>
+    {
+        self.storage    = storage;
+        self.file_name  = file_name.to_string();
+        self.lock       = Some(lock);
+        self.read_only  = false;
+        self.invi_dirty = false;
+        self.file_stamp = lock::get_stamp(file_name)?;
+        self.seen_stamp = None;
+        self.last_save  = Instant::now();
+        self.recovery_pending = false;
+        self.history.mark_saved();
+        return Ok(());
+    }
+
+    /// True if autosave is on and the interval passed since the last save
+    pub fn autosave_due(&self) -> bool
+    {
+        return match self.autosave
+        {
+            Some(interval) => self.invi_dirty && !self.read_only && !self.recovery_pending && self.last_save.elapsed() >= interval,
+            None => false
+        };
+    }
+
+    /// Called for every tick event, checks the open file for external changes about once a second
+    pub fn tick(&mut self)
+    {
+        self.ticks = self.ticks.wrapping_add(1);
+        if !self.ticks.is_multiple_of(WATCH_TICKS) {return;}
+        if lock::is_unchanged(&self.file_name, &self.file_stamp) {return;}
+        if self.seen_stamp.is_some() && lock::is_unchanged(&self.file_name, &self.seen_stamp) {return;}
+
+        let stamp = match lock::get_stamp(&self.file_name)
+        {
+            Ok(stamp) => stamp,
+            Err(_) => return
+        };
+
+        // the same content with a new modification time, e.g. after a touch, it is not hashed again
+        if stamp == self.file_stamp {self.file_stamp = stamp; return;}
+        if stamp == self.seen_stamp {self.seen_stamp = stamp; return;}
+
+        if self.invi_dirty
+        {
+            self.write_to_terminal(&format!("Conflict: {} was changed on disk and there are unwritten changes here!\n", self.file_name));
+            self.write_to_terminal("    :e! discards your changes, :w! overwrites the file, :w <file> saves your changes elsewhere\n");
+        }
+        else
+        {
+            self.write_to_terminal(&format!("{} was changed on disk, reload it with :e!\n", self.file_name));
+        }
+
+        self.seen_stamp = stamp;
+    }
+
     pub fn clear_terminal(&mut self)
     {
         self.term_txt.clear();
@@ -71,6 +231,14 @@ impl AppContext
         self.gui_dirty = true;
     }
 
+    /// Writes the message of a failed command to the terminal
+    pub fn write_error(&mut self, msg : &str)
+    {
+        self.errors += 1;
+        self.write_to_terminal(msg);
+        self.write_to_terminal("\n");
+    }
+
     pub fn check_if_changed<B: Backend>(&mut self, terminal: &Terminal<B>)
     {
         if self.size_term != terminal.size().unwrap()
@@ -97,7 +265,11 @@ impl AppContext
 
 pub fn draw<B: Backend>(terminal: &mut Terminal<B>, context: &mut AppContext) 
 {
-    if context.need_redraw() != true {return;}
+    if !context.need_redraw() {return;}
+
+    let input_title = format!(" Input - {}{}{}{} ", context.file_name,
+                              if context.read_only {" [RO]"} else {""}, if context.invi_dirty {" [+]"} else {""},
+                              if context.normal_mode {" -- NORMAL --"} else {""});
 
     terminal.draw(|mut f| 
     {
@@ -109,13 +281,13 @@ pub fn draw<B: Backend>(terminal: &mut Terminal<B>, context: &mut AppContext)
         match context.layout
         {
             InviLayout::Terminal => {draw_terminal (&mut f, chunks[0], &context.term_txt);}
-            InviLayout::Search => {draw_first_tab (&mut f, chunks[0]);}
+            InviLayout::Search => {draw_search (&mut f, chunks[0],context);}
             InviLayout::Overview => {draw_overview (&mut f, chunks[0],context);}
         }
        
        Paragraph::new([Text::raw(&context.txt_input)].iter())
                 .style(Style::default().fg(Color::Yellow))
-                .block(Block::default().borders(Borders::ALL).title("Input"))
+                .block(Block::default().borders(Borders::ALL).title(&input_title))
                 .render(&mut f, chunks[1]);
     }).unwrap();
 }
@@ -123,6 +295,7 @@ pub fn draw<B: Backend>(terminal: &mut Terminal<B>, context: &mut AppContext)
 fn draw_overview<B>(f: &mut Frame<B>, area: Rect, context : &AppContext) where B: Backend,
 {
     let style = Style::default().fg(Color::White).bg(Color::Reset);
+    let low_style = Style::default().fg(Color::LightRed).bg(Color::Reset);
 
     let main_chunks = Layout::default()
     .direction(Direction::Vertical)
@@ -151,11 +324,11 @@ fn draw_overview<B>(f: &mut Frame<B>, area: Rect, context : &AppContext) where B
 
     let items = context.inventory.items.iter().map(|value| 
     {
-        let txt = format!("{:04X} {:04X} : {}",value.id_cont, value.id, value.name); 
+        let txt = format!("{:04X} {:04X} : {} ({})",value.id_cont, value.id, value.name, value.quantity_str()); 
         Text::styled
         (
             txt,
-            style
+            if value.is_low() {low_style} else {style}
         )
     });
 
@@ -227,22 +400,74 @@ fn draw_terminal<B>(f: &mut Frame<B>, area: Rect, msg : &str) where B: Backend,
         .render(f, chunks[0]);
 }
 
-fn draw_first_tab<B>(f: &mut Frame<B>, area: Rect) where B: Backend,
+/// Builds one line per hit, the part of the name matching the key word is highlighted
+fn search_lines<'a>(hits : Vec<(String, &str)>, key_word : &str) -> Vec<Text<'a>>
 {
+    let style = Style::default().fg(Color::White).bg(Color::Reset);
+    let highlight = Style::default().fg(Color::LightGreen).modifier(Modifier::BOLD);
+    let mut lines = Vec::new();
+
+    for (path, name) in hits
+    {
+        let offset = path.len() - name.len();
+
+        match match_range(name, key_word)
+        {
+            Some((start, end)) =>
+            {
+                lines.push(Text::styled(path[..offset + start].to_string(), style));
+                lines.push(Text::styled(path[offset + start..offset + end].to_string(), highlight));
+                lines.push(Text::styled(format!("{}\n", &path[offset + end..]), style));
+            }
+            None => lines.push(Text::styled(format!("{}\n", path), style))
+        }
+    }
+
+    return lines;
+}
+
+fn draw_search<B>(f: &mut Frame<B>, area: Rect, context : &AppContext) where B: Backend,
+{
+    let inv = &context.inventory;
+    let result = search(&context.search_key, inv);
+    let key_word = context.search_key.trim_start_matches('#');
+
+    let main_chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+    .split(area);
+
     let chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-        .split(area);
+    .direction(Direction::Horizontal)
+    .constraints([Constraint::Percentage(25), Constraint::Percentage(25),Constraint::Percentage(25),Constraint::Percentage(25)].as_ref())
+    .split(main_chunks[0]);
 
-    Paragraph::new([Text::raw("This is just some text, This is just some text,This is just some text, This is just some text")].iter())
-        .style(Style::default().fg(Color::Yellow))
-        .block(Block::default().borders(Borders::ALL).title("Input"))
-        .render(f, chunks[1]);
+    let comp = search_lines(result.compartments.iter()
+        .map(|c| (format!("{:04X} : {}", c.id, c.name), c.name.as_str())).collect(), key_word);
 
-    Paragraph::new([Text::raw("This is just some other")].iter())
-        .style(Style::default().fg(Color::Yellow))
-        .block(Block::default().borders(Borders::ALL).title("Input"))
-        .render(f, chunks[0]);
+    let conts = search_lines(result.containers.iter()
+        .map(|c| (format!("{:04X} : {}", c.id, inv.container_path(c)), c.name.as_str())).collect(), key_word);
+
+    let items = search_lines(result.items.iter()
+        .map(|i| (format!("{:04X} : {}", i.id, inv.item_path(i)), i.name.as_str())).collect(), key_word);
+
+    let tags = search_lines(result.tags.iter()
+        .map(|t| (format!("{:04X} : {}", t.id, t.name), t.name.as_str())).collect(), key_word);
+
+    let columns = [(comp, result.compartments.len(), "Compartments"), (conts, result.containers.len(), "Containers"),
+                   (items, result.items.len(), "Items"), (tags, result.tags.len(), "Tags")];
+
+    for (index, (lines, cnt, title)) in columns.iter().enumerate()
+    {
+        Paragraph::new(lines.iter())
+            .block(Block::default().borders(Borders::ALL).title(&format!(" {} ({}) ", title, cnt)))
+            .render(f, chunks[index]);
+    }
+
+    Paragraph::new([Text::raw(&context.term_txt)].iter())
+        .style(Style::default().fg(Color::Cyan))
+        .block(Block::default().borders(Borders::ALL).title(&format!(" Search: {} ", context.search_key)))
+        .render(f, main_chunks[1]);
 }
 
 /* ****************************+ textfield handling ****************************+ */
@@ -257,7 +482,7 @@ pub fn get_input_str_and_clear(gui_context : &mut AppContext) -> String
     return tmp;
 }
 
-fn get_target_index(in_str : &mut String, index : usize) -> usize
+fn get_target_index(in_str : &str, index : usize) -> usize
 {
     let mut target_index : usize = 0;
     let mut position = index;
@@ -272,7 +497,7 @@ fn get_target_index(in_str : &mut String, index : usize) -> usize
     return target_index;
 }
 
-fn get_len_any(in_str : & String) -> usize
+fn get_len_any(in_str : &str) -> usize
 {
     let mut cnt : usize = 0; 
 
@@ -301,8 +526,31 @@ pub fn set_txt_input(context : &mut AppContext, msg : String)
     context.gui_dirty = true;
 }
 
+/// Keys in normal mode, ':' or 'i' go back to typing commands
+fn handle_normal_key(key : Key, context : &mut AppContext)
+{
+    match key
+    {
+        Key::Char(':') =>
+        {
+            context.normal_mode = false;
+            set_txt_input(context, ":".to_string());
+        }
+
+        Key::Char('i') =>
+        {
+            context.normal_mode = false;
+            context.gui_dirty = true;
+        }
+
+        _ => {}
+    }
+}
+
 pub fn handle_input_key (key : Key, context : &mut AppContext)
 {
+    if context.normal_mode {return handle_normal_key(key, context);}
+
     match key 
     {
         Key::Char(c) =>
@@ -339,6 +587,7 @@ pub fn handle_input_key (key : Key, context : &mut AppContext)
             context.txt_input.clear();
             context.cursor_pos = 0;
             context.scroll_back = 0;
+            context.normal_mode = true;
         }
 
         Key::Backspace => 
@@ -372,7 +621,7 @@ pub fn handle_input_key (key : Key, context : &mut AppContext)
 
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use termion::event::Key;
 use termion::input::TermRead;
@@ -425,18 +674,11 @@ impl Events
             thread::spawn(move || 
             {
                 let stdin = io::stdin();
-                for evt in stdin.keys() 
+                for key in stdin.keys().flatten() 
                 {
-                    match evt 
+                    if tx.send(Event::Input(key)).is_err() 
                     {
-                        Ok(key) => 
-                        {
-                            if let Err(_) = tx.send(Event::Input(key)) 
-                            {
-                                return;
-                            }
-                        }
-                        Err(_) => {}
+                        return;
                     }
                 }
             })
@@ -469,3 +711,31 @@ impl Events
         self.rx.recv()
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn contents(lines : Vec<Text>) -> Vec<String>
+    {
+        return lines.into_iter().map(|line| match line {Text::Raw(text) | Text::Styled(text, _) => text.to_string()}).collect();
+    }
+
+    #[test]
+    fn highlights_the_match_in_the_name()
+    {
+        let hits = vec![("0001 : Shelf/Zählerchip".to_string(), "Zählerchip"), ("0002 : Zählerkasten".to_string(), "Zählerkasten")];
+
+        assert_eq!(contents(search_lines(hits, "ÄHLER")),
+                   vec!["0001 : Shelf/Z", "ähler", "chip\n", "0002 : Z", "ähler", "kasten\n"]);
+    }
+
+    #[test]
+    fn keeps_lines_without_a_match()
+    {
+        // a hit pulled in by its tag does not contain the key word
+        let hits = vec![("0003 : Drawer/Bag".to_string(), "Bag")];
+        assert_eq!(contents(search_lines(hits, "esd")), vec!["0003 : Drawer/Bag\n"]);
+    }
+}
@@ -0,0 +1,187 @@
+use std::mem;
+
+use crate::inventory::{Compartment, Container, EntityKind, IdType, Inventory, Item, Tag};
+
+/// Number of changes which can be undone, the oldest ones are dropped first
+const MAX_HISTORY: usize = 100;
+
+/// An entity of any kind, as it is kept in the history
+#[derive(Clone)]
+pub enum Entity
+{
+    Compartment(Compartment),
+    Container(Container),
+    Item(Item),
+    Tag(Tag)
+}
+
+/// The inverse of a single change of the inventory, applying it reverts the change
+#[derive(Clone)]
+pub enum Revert
+{
+    /// puts the removed entity back at its index
+    Insert(usize, Entity),
+    /// removes the added entity at the index
+    Remove(EntityKind, usize),
+    /// puts the previous version of the entity back at its index
+    Replace(usize, Entity),
+    /// the id counters before the command gave out new ids
+    Counters([IdType; 4])
+}
+
+/// The inverse changes of one command, applying them yields the changes to redo it
+struct Change
+{
+    command : String,
+    reverts : Vec<Revert>,
+    /// the state the inventory had at that point
+    state   : u64
+}
+
+/// The undo and redo stacks of the open inventory. Every state of the inventory gets its own
+/// number, the state which was written is 0, so it is known whether undoing or redoing leads back to it.
+pub struct History
+{
+    undo  : Vec<Change>,
+    redo  : Vec<Change>,
+    state : u64,
+    next  : u64
+}
+
+impl History
+{
+    pub fn new() -> History
+    {
+        return History{undo : Vec::new(), redo : Vec::new(), state : 0, next : 0};
+    }
+
+    pub fn get_state(&self) -> u64
+    {
+        return self.state;
+    }
+
+    /// Remembers how to revert the command, nothing can be redone afterwards
+    pub fn record(&mut self, command : &str, reverts : Vec<Revert>)
+    {
+        if self.undo.len() == MAX_HISTORY {self.undo.remove(0);}
+
+        self.undo.push(Change{command : command.trim().to_string(), reverts, state : self.state});
+        self.redo.clear();
+
+        self.next += 1;
+        self.state = self.next;
+    }
+
+    /// Reverts the last change of the inventory, returns the command which is undone
+    pub fn undo(&mut self, inventory : &mut Inventory) -> Option<String>
+    {
+        let change = self.undo.pop()?;
+        let change = self.apply(change, inventory);
+        let command = change.command.clone();
+        self.redo.push(change);
+        return Some(command);
+    }
+
+    /// Applies the last undone change again, returns its command
+    pub fn redo(&mut self, inventory : &mut Inventory) -> Option<String>
+    {
+        let change = self.redo.pop()?;
+        let change = self.apply(change, inventory);
+        let command = change.command.clone();
+        self.undo.push(change);
+        return Some(command);
+    }
+
+    /// Reverts the change, it holds the changes to go back afterwards
+    fn apply(&mut self, mut change : Change, inventory : &mut Inventory) -> Change
+    {
+        inventory.revert(mem::take(&mut change.reverts));
+        change.reverts = inventory.take_reverts();
+        mem::swap(&mut change.state, &mut self.state);
+        return change;
+    }
+
+    /// The current state was written to the file. The recovery journal starts over with every write,
+    /// so the history does as well, a :u replayed from the journal can only undo a later command then.
+    pub fn mark_saved(&mut self)
+    {
+        *self = History::new();
+    }
+
+    /// True if the inventory is in the state which was written last
+    pub fn is_saved(&self) -> bool
+    {
+        return self.state == 0;
+    }
+}
+
+impl Default for History
+{
+    fn default() -> History
+    {
+        return History::new();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Runs the change as a command, the way dispatch_input does it
+    fn run(history : &mut History, inventory : &mut Inventory, command : &str, change : impl FnOnce(&mut Inventory))
+    {
+        inventory.begin();
+        change(inventory);
+        let reverts = inventory.commit(command);
+        history.record(command, reverts);
+    }
+
+    fn undo(history : &mut History, inventory : &mut Inventory, redo : bool) -> Option<String>
+    {
+        inventory.begin();
+        let command = if redo {history.redo(inventory)} else {history.undo(inventory)};
+        inventory.commit(":u");
+        return command;
+    }
+
+    fn names(inventory : &Inventory) -> (Vec<String>, Vec<String>, Vec<String>)
+    {
+        return (inventory.compartments.iter().map(|c| c.name.clone()).collect(),
+                inventory.containers.iter().map(|c| c.name.clone()).collect(),
+                inventory.items.iter().map(|i| i.name.clone()).collect());
+    }
+
+    #[test]
+    fn undoes_and_redoes_in_order()
+    {
+        let mut history = History::new();
+        let mut inventory = Inventory::new();
+        let empty = names(&inventory);
+
+        run(&mut history, &mut inventory, ":acomp Shelf", |inv| inv.add_compartment("Shelf"));
+        run(&mut history, &mut inventory, ":acont Box 0", |inv| inv.add_container("Box", 0, Vec::new()).unwrap());
+        run(&mut history, &mut inventory, ":aitem Screw 0", |inv| inv.add_item("Screw", 0, 1, "").unwrap());
+        let full = names(&inventory);
+        assert!(!history.is_saved());
+
+        run(&mut history, &mut inventory, ":dcomp! 0", |inv| inv.remove_compartment(0, true).unwrap());
+        assert_eq!(names(&inventory), (vec![], vec![], vec![]));
+
+        assert_eq!(undo(&mut history, &mut inventory, false).as_deref(), Some(":dcomp! 0"));
+        assert_eq!(names(&inventory), full);
+        assert!(inventory.check().is_empty());
+
+        for _ in 0..3 {undo(&mut history, &mut inventory, false);}
+        assert_eq!(names(&inventory), empty);
+        assert!(history.is_saved());
+        assert_eq!(undo(&mut history, &mut inventory, false), None);
+
+        for _ in 0..3 {undo(&mut history, &mut inventory, true);}
+        assert_eq!(names(&inventory), full);
+
+        // a new change drops what could be redone
+        run(&mut history, &mut inventory, ":atag Metric", |inv| inv.add_tag("Metric"));
+        assert_eq!(undo(&mut history, &mut inventory, true), None);
+    }
+}
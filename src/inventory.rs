@@ -1,244 +1,1662 @@
 use serde::{Serialize, Deserialize};
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::io::{self, Write};
 use std::fs;
-use whoami;
+use std::mem;
+use std::env;
+use std::fmt;
+use std::collections::{HashMap, HashSet};
+use crate::error::{GenericError, UsageError};
+use crate::storage;
+use crate::migrate;
+use crate::audit::{self, Audited};
+use crate::history::{Entity, Revert};
 
 pub static FILE_NAME: &str = "base.json";
 
 pub type IdType = u32;
 
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Inventory
 {
+    #[serde(default)]
+    version             : u32,
     pub compartments    : Vec<Compartment>,
     pub containers      : Vec<Container>,
     pub tags            : Vec<Tag>,
     pub items           : Vec<Item>,
+    /// every change of an entity, oldest first
+    #[serde(default)]
+    pub log             : Vec<LogEntry>,
     cnt_compartment : IdType,
     cnt_container   : IdType,
     cnt_item        : IdType,
-    cnt_tag         : IdType
+    cnt_tag         : IdType,
+    /// the changes of the running command, see begin
+    #[serde(skip)]
+    pending         : Option<Pending>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Compartment
 {
     pub name       : String,
     pub id         : IdType,
-    containers : Vec<IdType>
+    containers : Vec<IdType>,
+    #[serde(default)]
+    tags       : Vec<IdType>,
+    #[serde(default)]
+    pub audit  : Audit
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Container
 {
     pub name  : String,
     pub id    : IdType,
     pub id_comp   : IdType,
     items : Vec<IdType>,
-    tags  : Vec<IdType>
+    tags  : Vec<IdType>,
+    #[serde(default)]
+    pub audit : Audit
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Item
 {
     pub name    : String,
     pub id      : IdType,
     pub id_cont : IdType,
+    #[serde(default = "default_quantity")]
+    pub quantity : u32,
+    #[serde(default)]
+    pub unit     : String,
+    #[serde(default)]
+    pub min_quantity : u32,
+    #[serde(default)]
+    tags         : Vec<IdType>,
+    #[serde(default)]
+    pub audit    : Audit
 }
 
-#[derive(Serialize, Deserialize)]
+fn default_quantity() -> u32 { return 1; }
+
+impl Compartment
+{
+    pub fn get_tags(&self) -> &[IdType] {return &self.tags;}
+}
+
+impl Container
+{
+    pub fn get_tags(&self) -> &[IdType] {return &self.tags;}
+}
+
+impl Item
+{
+    pub fn get_tags(&self) -> &[IdType] {return &self.tags;}
+
+    /// Quantity with its unit, e.g. "200 pcs"
+    pub fn quantity_str(&self) -> String
+    {
+        if self.unit.is_empty() {return format!("{}", self.quantity);}
+        return format!("{} {}", self.quantity, self.unit);
+    }
+
+    /// True if the stock is below the minimum stock level
+    pub fn is_low(&self) -> bool
+    {
+        return self.quantity < self.min_quantity;
+    }
+
+    pub fn shortfall(&self) -> u32
+    {
+        return self.min_quantity.saturating_sub(self.quantity);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Tag
 {
-    pub name  : String,
-    pub id    : IdType
+    pub name  : String,
+    pub id    : IdType,
+    #[serde(default)]
+    pub audit : Audit
+}
+
+/// When and by whom an entity was created and last changed, empty for entities older than the audit.
+/// The times are RFC 3339 in UTC, see audit::Actor.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Audit
+{
+    pub created_at  : String,
+    pub created_by  : String,
+    pub modified_at : String,
+    pub modified_by : String
+}
+
+/// One entry of the change log, action is created, changed or removed
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogEntry
+{
+    pub time    : String,
+    pub user    : String,
+    pub kind    : EntityKind,
+    pub id      : IdType,
+    pub name    : String,
+    pub action  : String,
+    /// the command which made the change
+    pub command : String
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityKind
+{
+    Compartment,
+    Container,
+    Item,
+    Tag
+}
+
+impl EntityKind
+{
+    /// Parses the kind names used by the commands: comp, cont, item and tag, the full names and their plurals
+    pub fn parse(kind : &str) -> Result<EntityKind, UsageError>
+    {
+        return match kind
+        {
+            "comp" | "compartment" | "compartments" => Ok(EntityKind::Compartment),
+            "cont" | "container"   | "containers"   => Ok(EntityKind::Container),
+            "item" | "items"                        => Ok(EntityKind::Item),
+            "tag"  | "tags"                         => Ok(EntityKind::Tag),
+            _ => Err(UsageError::new(format!("Unknown kind \"{}\", use comp, cont, item or tag!!!", kind)))
+        };
+    }
+}
+
+impl fmt::Display for EntityKind
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let name = match self
+        {
+            EntityKind::Compartment => "compartment",
+            EntityKind::Container   => "container",
+            EntityKind::Item        => "item",
+            EntityKind::Tag         => "tag"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//trait IdObject{fn get_id(&self) -> u32; }
+//impl IdObject for Tag        { fn get_id(&self) -> IdType {return self.id;} }
+//impl IdObject for Item       { fn get_id(&self) -> IdType {return self.id;} }
+//impl IdObject for Container  { fn get_id(&self) -> IdType {return self.id;} }
+//impl IdObject for Compartment{ fn get_id(&self) -> IdType {return self.id;} }
+
+pub struct SearchResult <'a>
+{
+    pub compartments    : Vec<&'a Compartment>,
+    pub containers      : Vec<&'a Container>,
+    pub tags            : Vec<&'a Tag>,
+    pub items           : Vec<&'a Item>,
+}
+
+impl <'a> SearchResult <'a>
+{
+    pub fn is_empty(&self) -> bool
+    {
+        return self.compartments.is_empty() && self.containers.is_empty() && self.tags.is_empty() && self.items.is_empty();
+    }
+
+    /// Keeps only the entities of one kind
+    pub fn only(mut self, kind : EntityKind) -> SearchResult<'a>
+    {
+        if kind != EntityKind::Compartment {self.compartments.clear();}
+        if kind != EntityKind::Container   {self.containers.clear();}
+        if kind != EntityKind::Item        {self.items.clear();}
+        if kind != EntityKind::Tag         {self.tags.clear();}
+        return self;
+    }
+}
+
+/// The entities with the id, of one kind or of all kinds
+pub fn select_id<'a>(inv : &'a Inventory, kind : Option<EntityKind>, id : IdType) -> SearchResult<'a>
+{
+    let result = SearchResult
+    {
+        compartments : inv.compartments.iter().filter(|c| c.id == id).collect(),
+        containers   : inv.containers.iter().filter(|c| c.id == id).collect(),
+        items        : inv.items.iter().filter(|i| i.id == id).collect(),
+        tags         : inv.tags.iter().filter(|t| t.id == id).collect()
+    };
+
+    return match kind {Some(kind) => result.only(kind), None => result};
+}
+
+/// Every entity of the inventory as a search result
+pub fn list_all<'a>(inv : &'a Inventory) -> SearchResult<'a>
+{
+    return SearchResult
+    {
+        compartments : inv.compartments.iter().collect(),
+        containers   : inv.containers.iter().collect(),
+        items        : inv.items.iter().collect(),
+        tags         : inv.tags.iter().collect()
+    };
+}
+
+/// Byte range of the first case-insensitive occurrence of the key word in the name
+pub fn match_range(name : &str, key_word : &str) -> Option<(usize, usize)>
+{
+    let key_word = key_word.trim().to_lowercase();
+    if key_word.is_empty() {return None;}
+
+    for (start, _) in name.char_indices()
+    {
+        let rest = &name[start..];
+        let mut matched = String::new();
+
+        for (offset, ch) in rest.char_indices()
+        {
+            matched.extend(ch.to_lowercase());
+            if matched == key_word {return Some((start, start + offset + ch.len_utf8()));}
+            if !key_word.starts_with(&matched) {break;}
+        }
+    }
+
+    return None;
+}
+
+fn name_matches(name : &str, key_word : &str) -> bool
+{
+    return name.to_lowercase().contains(key_word);
+}
+
+/// Searches all entities for the key word (case-insensitive substring of the name).
+/// Every compartment, container and item carrying a matching tag is part of the result as well.
+/// A key word starting with '#' only matches tags, e.g. "#esd" lists everything tagged ESD.
+pub fn search<'a>(key_word : &str, inv :&'a Inventory) -> SearchResult <'a>
+{
+    let key_word = key_word.trim().to_lowercase();
+    let tags_only = key_word.starts_with('#');
+    let key_word = key_word.trim_start_matches('#');
+
+    let tags : Vec<&Tag> = inv.tags.iter().filter(|t| name_matches(&t.name, key_word)).collect();
+    let tagged = |entity_tags : &Vec<IdType>| tags.iter().any(|t| entity_tags.contains(&t.id));
+    let named  = |name : &str| !tags_only && name_matches(name, key_word);
+
+    return SearchResult 
+    {
+        compartments    : inv.compartments.iter().filter(|c| named(&c.name) || tagged(&c.tags)).collect(),
+        containers      : inv.containers.iter().filter(|c| named(&c.name) || tagged(&c.tags)).collect(),
+        items           : inv.items.iter().filter(|i| named(&i.name) || tagged(&i.tags)).collect(),
+        tags
+    }
+}
+
+impl Default for Inventory
+{
+    fn default() -> Inventory
+    {
+        return Inventory::new();
+    }
+}
+
+fn check_name(name : &str) -> Result<(),String>
+{
+    if name.trim().is_empty()
+    {
+        return Err("The name must not be empty!!!".to_string());
+    }
+    return Ok(());
+}
+
+impl Inventory
+{
+    pub fn new() -> Inventory
+    {
+        return Inventory
+        {
+            version         : migrate::FORMAT_VERSION,
+            compartments    : Vec::new(),
+            containers      : Vec::new(),
+            tags            : Vec::new(),
+            items           : Vec::new(),
+            log             : Vec::new(),
+            cnt_compartment : 0 as IdType,
+            cnt_container   : 0 as IdType,
+            cnt_item        : 0 as IdType,
+            cnt_tag         : 0 as IdType,
+            pending         : None
+        };
+    }
+
+    pub fn get_compartment(&self, id : IdType) -> Option<&Compartment>
+    {
+        return self.compartments.iter().find(|c| c.id == id);
+    }
+
+    pub fn get_container(&self, id : IdType) -> Option<&Container>
+    {
+        return self.containers.iter().find(|c| c.id == id);
+    }
+
+    /// Location of a container as "compartment → container"
+    pub fn container_path(&self, cont : &Container) -> String
+    {
+        let comp_name = self.get_compartment(cont.id_comp).map_or("????", |c| &c.name);
+        return format!("{} → {}", comp_name, cont.name);
+    }
+
+    /// Location of an item as "compartment → container → item"
+    pub fn item_path(&self, item : &Item) -> String
+    {
+        return match self.get_container(item.id_cont)
+        {
+            Some(cont) => format!("{} → {}", self.container_path(cont), item.name),
+            None       => format!("???? → ???? → {}", item.name)
+        };
+    }
+
+    pub fn check_tags_ids(&self, ids : &[IdType]) -> Result<(),String>
+    {
+        'main_loop : for id in ids
+        {
+            for tag in self.tags.iter()
+            {
+                if &tag.id == id 
+                {
+                    continue 'main_loop;
+                }
+            }
+            return Err(format!("{}",id));
+        }
+        return Ok(());
+    }
+
+    /// The index of the entity with the id in the list of its kind
+    fn find_index<T : Audited>(&self, id : IdType) -> Result<usize,String>
+    {
+        return match T::get_list(self).iter().position(|e| e.get_id() == id)
+        {
+            Some(index) => Ok(index),
+            None => Err(format!("The {} with the id: {} was not found!!!", T::KIND, id))
+        };
+    }
+
+    pub fn add_tag(&mut self, name : &str)
+    {
+        self.save_counters();
+        let tag = Tag
+        {
+            name  : String::from(name),
+            id : self.cnt_tag,
+            audit : Audit::default()
+        };
+        self.push_entity(tag);
+        self.cnt_tag += 1;
+    }
+
+    pub fn add_item(&mut self, name : &str, con_id : IdType, quantity : u32, unit : &str) -> Result<(),String>
+    {
+        let cont_index = self.find_index::<Container>(con_id)?;
+        let item_id = self.cnt_item;
+
+        self.save_counters();
+        let item = Item
+        {
+            name  : String::from(name),
+            id : item_id,
+            id_cont : con_id,
+            quantity,
+            unit : String::from(unit),
+            min_quantity : 0,
+            tags : Vec::new(),
+            audit : Audit::default()
+        };
+        self.push_entity(item);
+
+        self.change_entity::<Container>(cont_index).items.push(item_id);
+        self.cnt_item += 1;
+
+        return Ok(());
+    }
+
+    pub fn add_container(&mut self, name : &str, com_id : IdType, tags : Vec<IdType>) -> Result<(),String>
+    {
+        if let Err(e) = self.check_tags_ids(&tags) {return Err(format!("Check the tag ids, {} was not found!!!",e));}
+
+        let comp_index = self.find_index::<Compartment>(com_id)?;
+        let cont_id = self.cnt_container;
+
+        self.save_counters();
+        let cont = Container
+        {
+            name  : String::from(name),
+            id : cont_id,
+            id_comp : com_id,
+            items : Vec::new(),
+            tags,
+            audit : Audit::default()
+        };
+        self.push_entity(cont);
+
+        self.change_entity::<Compartment>(comp_index).containers.push(cont_id);
+        self.cnt_container += 1;
+
+        return Ok(());
+    }
+
+    pub fn add_compartment(&mut self, name : &str)
+    {
+        self.save_counters();
+        let comp = Compartment
+        {
+            name  : String::from(name),
+            id : self.cnt_compartment,
+            containers : Vec::new(),
+            tags : Vec::new(),
+            audit : Audit::default()
+        };
+        self.push_entity(comp);
+        self.cnt_compartment += 1;
+    }
+
+    pub fn rename_item(&mut self, id : IdType, name : &str) -> Result<(),String>
+    {
+        check_name(name)?;
+        let index = self.find_index::<Item>(id)?;
+        if self.items[index].name != name {self.change_entity::<Item>(index).name = String::from(name);}
+        return Ok(());
+    }
+
+    pub fn rename_container(&mut self, id : IdType, name : &str) -> Result<(),String>
+    {
+        check_name(name)?;
+        let index = self.find_index::<Container>(id)?;
+        if self.containers[index].name != name {self.change_entity::<Container>(index).name = String::from(name);}
+        return Ok(());
+    }
+
+    pub fn rename_compartment(&mut self, id : IdType, name : &str) -> Result<(),String>
+    {
+        check_name(name)?;
+        let index = self.find_index::<Compartment>(id)?;
+        if self.compartments[index].name != name {self.change_entity::<Compartment>(index).name = String::from(name);}
+        return Ok(());
+    }
+
+    pub fn rename_tag(&mut self, id : IdType, name : &str) -> Result<(),String>
+    {
+        check_name(name)?;
+        let index = self.find_index::<Tag>(id)?;
+        if self.tags[index].name != name {self.change_entity::<Tag>(index).name = String::from(name);}
+        return Ok(());
+    }
+
+    /// Removes `amount` from the stock of an item, fails if there is not enough in stock
+    pub fn take_item(&mut self, id : IdType, amount : u32) -> Result<(),String>
+    {
+        let index = self.find_index::<Item>(id)?;
+        let item = &self.items[index];
+        if item.quantity < amount
+        {
+            return Err(format!("Only {} of {} in stock!!!", item.quantity_str(), item.name));
+        }
+        if amount != 0 {self.change_entity::<Item>(index).quantity -= amount;}
+        return Ok(());
+    }
+
+    pub fn put_item(&mut self, id : IdType, amount : u32) -> Result<(),String>
+    {
+        let index = self.find_index::<Item>(id)?;
+        let item = &self.items[index];
+        let quantity = match item.quantity.checked_add(amount)
+        {
+            Some(quantity) => quantity,
+            None => return Err(format!("The quantity of {} would overflow!!!", item.name))
+        };
+        if amount != 0 {self.change_entity::<Item>(index).quantity = quantity;}
+        return Ok(());
+    }
+
+    pub fn set_quantity(&mut self, id : IdType, quantity : u32) -> Result<(),String>
+    {
+        let index = self.find_index::<Item>(id)?;
+        if self.items[index].quantity != quantity {self.change_entity::<Item>(index).quantity = quantity;}
+        return Ok(());
+    }
+
+    pub fn set_min_quantity(&mut self, id : IdType, min_quantity : u32) -> Result<(),String>
+    {
+        let index = self.find_index::<Item>(id)?;
+        if self.items[index].min_quantity != min_quantity {self.change_entity::<Item>(index).min_quantity = min_quantity;}
+        return Ok(());
+    }
+
+    /// All items whose stock is below their minimum stock level
+    pub fn restock_list(&self) -> Vec<&Item>
+    {
+        return self.items.iter().filter(|i| i.is_low()).collect();
+    }
+
+    pub fn set_unit(&mut self, id : IdType, unit : &str) -> Result<(),String>
+    {
+        let index = self.find_index::<Item>(id)?;
+        if self.items[index].unit != unit.trim() {self.change_entity::<Item>(index).unit = String::from(unit.trim());}
+        return Ok(());
+    }
+
+    /// The name and the audit of an entity
+    pub fn get_audit(&self, kind : EntityKind, id : IdType) -> Option<(&str, &Audit)>
+    {
+        return match kind
+        {
+            EntityKind::Compartment => self.compartments.iter().find(|c| c.id == id).map(|c| (c.name.as_str(), &c.audit)),
+            EntityKind::Container   => self.containers.iter().find(|c| c.id == id).map(|c| (c.name.as_str(), &c.audit)),
+            EntityKind::Item        => self.items.iter().find(|i| i.id == id).map(|i| (i.name.as_str(), &i.audit)),
+            EntityKind::Tag         => self.tags.iter().find(|t| t.id == id).map(|t| (t.name.as_str(), &t.audit))
+        };
+    }
+
+    /// The index and the tags of a compartment, container or item
+    fn get_tags(&self, kind : EntityKind, id : IdType) -> Result<(usize, &[IdType]),String>
+    {
+        return match kind
+        {
+            EntityKind::Compartment => self.find_index::<Compartment>(id).map(|i| (i, self.compartments[i].get_tags())),
+            EntityKind::Container   => self.find_index::<Container>(id).map(|i| (i, self.containers[i].get_tags())),
+            EntityKind::Item        => self.find_index::<Item>(id).map(|i| (i, self.items[i].get_tags())),
+            EntityKind::Tag         => Err("Tags can not be tagged!!!".to_string())
+        };
+    }
+
+    fn change_tags(&mut self, kind : EntityKind, index : usize) -> &mut Vec<IdType>
+    {
+        return match kind
+        {
+            EntityKind::Compartment => &mut self.change_entity::<Compartment>(index).tags,
+            EntityKind::Container   => &mut self.change_entity::<Container>(index).tags,
+            _                       => &mut self.change_entity::<Item>(index).tags
+        };
+    }
+
+    /// Attaches the tags to a compartment, container or item, tags already attached are skipped
+    pub fn tag_entity(&mut self, kind : EntityKind, id : IdType, tag_ids : &[IdType]) -> Result<(),String>
+    {
+        if let Err(e) = self.check_tags_ids(tag_ids) {return Err(format!("Check the tag ids, {} was not found!!!",e));}
+
+        let (index, tags) = self.get_tags(kind, id)?;
+        if tag_ids.iter().all(|t| tags.contains(t)) {return Ok(());}
+
+        let tags = self.change_tags(kind, index);
+        for tag_id in tag_ids
+        {
+            if !tags.contains(tag_id) {tags.push(*tag_id);}
+        }
+
+        return Ok(());
+    }
+
+    pub fn untag_entity(&mut self, kind : EntityKind, id : IdType, tag_ids : &[IdType]) -> Result<(),String>
+    {
+        let (index, tags) = self.get_tags(kind, id)?;
+        if !tags.iter().any(|t| tag_ids.contains(t)) {return Ok(());}
+
+        self.change_tags(kind, index).retain(|t| !tag_ids.contains(t));
+        return Ok(());
+    }
+
+    pub fn move_item(&mut self, id : IdType, con_id : IdType) -> Result<(),String>
+    {
+        let index = self.find_index::<Item>(id)?;
+        let to_index = self.find_index::<Container>(con_id)?;
+
+        let old_con_id = self.items[index].id_cont;
+        if old_con_id == con_id {return Ok(());}
+
+        if let Ok(from_index) = self.find_index::<Container>(old_con_id)
+        {
+            self.change_entity::<Container>(from_index).items.retain(|i| *i != id);
+        }
+
+        self.change_entity::<Container>(to_index).items.push(id);
+        self.change_entity::<Item>(index).id_cont = con_id;
+
+        return Ok(());
+    }
+
+    /// Moves every item of one container into another one
+    pub fn move_all_items(&mut self, from_con_id : IdType, to_con_id : IdType) -> Result<(),String>
+    {
+        let item_ids = self.containers[self.find_index::<Container>(from_con_id)?].items.clone();
+        self.find_index::<Container>(to_con_id)?;
+
+        // every item is checked first, so nothing is moved if one of them is missing
+        for id in item_ids.iter()
+        {
+            self.find_index::<Item>(*id)?;
+        }
+
+        for id in item_ids
+        {
+            self.move_item(id, to_con_id)?;
+        }
+
+        return Ok(());
+    }
+
+    pub fn move_container(&mut self, id : IdType, com_id : IdType) -> Result<(),String>
+    {
+        let index = self.find_index::<Container>(id)?;
+        let to_index = self.find_index::<Compartment>(com_id)?;
+
+        let old_com_id = self.containers[index].id_comp;
+        if old_com_id == com_id {return Ok(());}
+
+        if let Ok(from_index) = self.find_index::<Compartment>(old_com_id)
+        {
+            self.change_entity::<Compartment>(from_index).containers.retain(|c| *c != id);
+        }
+
+        self.change_entity::<Compartment>(to_index).containers.push(id);
+        self.change_entity::<Container>(index).id_comp = com_id;
+
+        return Ok(());
+    }
+
+    pub fn remove_item(&mut self, id : IdType) -> Result<(),String>
+    {
+        let index = self.find_index::<Item>(id)?;
+        let item = self.remove_entity::<Item>(index);
+
+        if let Ok(cont_index) = self.find_index::<Container>(item.id_cont)
+        {
+            self.change_entity::<Container>(cont_index).items.retain(|i| *i != id);
+        }
+
+        return Ok(());
+    }
+
+    /// Removes a container, with `cascade` all of its items are removed as well
+    pub fn remove_container(&mut self, id : IdType, cascade : bool) -> Result<(),String>
+    {
+        let index = self.find_index::<Container>(id)?;
+
+        if !self.containers[index].items.is_empty() && !cascade
+        {
+            return Err(format!("The container with the id: {} is not empty, remove it with :dcont! to delete its items as well", id));
+        }
+
+        let cont = self.remove_entity::<Container>(index);
+
+        // items listed by the container which do not exist are skipped
+        for item_id in cont.items.iter()
+        {
+            if let Ok(item_index) = self.find_index::<Item>(*item_id) {self.remove_entity::<Item>(item_index);}
+        }
+
+        if let Ok(comp_index) = self.find_index::<Compartment>(cont.id_comp)
+        {
+            self.change_entity::<Compartment>(comp_index).containers.retain(|c| *c != id);
+        }
+
+        return Ok(());
+    }
+
+    /// Removes a compartment, with `cascade` all of its containers and their items are removed as well
+    pub fn remove_compartment(&mut self, id : IdType, cascade : bool) -> Result<(),String>
+    {
+        let index = self.find_index::<Compartment>(id)?;
+
+        if !self.compartments[index].containers.is_empty() && !cascade
+        {
+            return Err(format!("The compartment with the id: {} is not empty, remove it with :dcomp! to delete its containers as well", id));
+        }
+
+        let comp = self.remove_entity::<Compartment>(index);
+
+        // containers listed by the compartment which do not exist are skipped
+        for cont_id in comp.containers
+        {
+            if self.find_index::<Container>(cont_id).is_ok() {self.remove_container(cont_id, true)?;}
+        }
+
+        return Ok(());
+    }
+
+    /// Removes a tag and strips it from every compartment, container and item
+    pub fn remove_tag(&mut self, id : IdType) -> Result<(),String>
+    {
+        let index = self.find_index::<Tag>(id)?;
+        self.remove_entity::<Tag>(index);
+
+        for index in 0..self.compartments.len()
+        {
+            if self.compartments[index].tags.contains(&id) {self.change_entity::<Compartment>(index).tags.retain(|t| *t != id);}
+        }
+        for index in 0..self.containers.len()
+        {
+            if self.containers[index].tags.contains(&id) {self.change_entity::<Container>(index).tags.retain(|t| *t != id);}
+        }
+        for index in 0..self.items.len()
+        {
+            if self.items[index].tags.contains(&id) {self.change_entity::<Item>(index).tags.retain(|t| *t != id);}
+        }
+
+        return Ok(());
+    }
+
+    /// Replaces the entities and the counters with the ones of another inventory, e.g. a backup.
+    /// The change log is kept and the replaced entities are recorded like any other change.
+    pub fn restore(&mut self, other : Inventory)
+    {
+        let counters = [other.cnt_compartment, other.cnt_container, other.cnt_item, other.cnt_tag];
+        self.replace_list(other.compartments);
+        self.replace_list(other.containers);
+        self.replace_list(other.items);
+        self.replace_list(other.tags);
+        self.set_counters(counters);
+    }
+}
+
+
+/* ****************************+ history ****************************+ */
+
+/// The changes of the running command
+#[derive(Clone, Default)]
+struct Pending
+{
+    /// the inverse of every change, in the order the changes were made
+    reverts  : Vec<Revert>,
+    /// the entities whose previous version is part of reverts already
+    saved    : HashSet<(EntityKind, IdType)>,
+    counters : bool,
+    /// the changed entities with their name before the command, None if they did not exist then
+    touched  : Vec<(EntityKind, IdType, Option<String>)>,
+    seen     : HashSet<(EntityKind, IdType)>
+}
+
+impl Inventory
+{
+    /// Starts recording the changes of a command. They are audited and handed to the undo history
+    /// by commit or reverted by rollback, without a running command nothing is recorded.
+    pub fn begin(&mut self)
+    {
+        self.pending = Some(Pending::default());
+    }
+
+    /// Stamps every entity the command changed, appends the changes to the log and returns the
+    /// changes needed to revert the command
+    pub fn commit(&mut self, command : &str) -> Vec<Revert>
+    {
+        let pending = match self.pending.take()
+        {
+            Some(pending) => pending,
+            None => return Vec::new()
+        };
+
+        if pending.touched.is_empty() {return pending.reverts;}
+
+        let actor = audit::Actor::now();
+        let command = command.trim();
+
+        for (kind, id, before) in pending.touched
+        {
+            match kind
+            {
+                EntityKind::Compartment => self.log_touched::<Compartment>(&actor, id, before, command),
+                EntityKind::Container   => self.log_touched::<Container>(&actor, id, before, command),
+                EntityKind::Item        => self.log_touched::<Item>(&actor, id, before, command),
+                EntityKind::Tag         => self.log_touched::<Tag>(&actor, id, before, command)
+            }
+        }
+
+        return pending.reverts;
+    }
+
+    /// Reverts everything the running command changed, e.g. because it failed halfway
+    pub fn rollback(&mut self)
+    {
+        if let Some(pending) = self.pending.take() {self.revert(pending.reverts);}
+    }
+
+    /// Applies the changes recorded by a command in reverse order
+    pub fn revert(&mut self, reverts : Vec<Revert>)
+    {
+        for revert in reverts.into_iter().rev() {self.apply(revert);}
+    }
+
+    /// The changes recorded so far by the running command, they are not recorded any more afterwards
+    pub fn take_reverts(&mut self) -> Vec<Revert>
+    {
+        return match self.pending.as_mut()
+        {
+            Some(pending) => {pending.saved.clear(); pending.counters = false; mem::take(&mut pending.reverts)}
+            None => Vec::new()
+        };
+    }
+
+    fn log_touched<T : Audited>(&mut self, actor : &audit::Actor, id : IdType, before : Option<String>, command : &str)
+    {
+        let (action, name) = match (T::get_list(self).iter().position(|e| e.get_id() == id), before)
+        {
+            (Some(index), before) =>
+            {
+                let entity = &mut T::get_list_mut(self)[index];
+                audit::stamp(entity.get_audit_mut(), actor, before.is_none());
+                (if before.is_none() {"created"} else {"changed"}, entity.get_name().to_string())
+            }
+            (None, Some(name)) => ("removed", name),
+            (None, None) => return
+        };
+
+        audit::log_change(&mut self.log, actor, T::KIND, id, &name, action, command);
+    }
+
+    fn record(&mut self, revert : Revert)
+    {
+        if let Some(pending) = self.pending.as_mut() {pending.reverts.push(revert);}
+    }
+
+    /// Remembers that the entity is changed by the command, `before` is its name if it existed before
+    fn touch(&mut self, kind : EntityKind, id : IdType, before : Option<&str>)
+    {
+        if let Some(pending) = self.pending.as_mut()
+        {
+            if pending.seen.insert((kind, id)) {pending.touched.push((kind, id, before.map(String::from)));}
+        }
+    }
+
+    fn save_counters(&mut self)
+    {
+        let counters = [self.cnt_compartment, self.cnt_container, self.cnt_item, self.cnt_tag];
+
+        if let Some(pending) = self.pending.as_mut()
+        {
+            if !pending.counters {pending.counters = true; pending.reverts.push(Revert::Counters(counters));}
+        }
+    }
+
+    fn set_counters(&mut self, counters : [IdType; 4])
+    {
+        if counters == [self.cnt_compartment, self.cnt_container, self.cnt_item, self.cnt_tag] {return;}
+
+        self.save_counters();
+        self.cnt_compartment = counters[0];
+        self.cnt_container   = counters[1];
+        self.cnt_item        = counters[2];
+        self.cnt_tag         = counters[3];
+    }
+
+    fn insert_entity<T : Audited>(&mut self, index : usize, entity : T)
+    {
+        let id = entity.get_id();
+        self.touch(T::KIND, id, None);
+
+        if let Some(pending) = self.pending.as_mut()
+        {
+            // removing the entity reverts every later change of it as well
+            pending.saved.insert((T::KIND, id));
+            pending.reverts.push(Revert::Remove(T::KIND, index));
+        }
+
+        T::get_list_mut(self).insert(index, entity);
+    }
+
+    fn push_entity<T : Audited>(&mut self, entity : T)
+    {
+        let index = T::get_list(self).len();
+        self.insert_entity(index, entity);
+    }
+
+    fn remove_entity<T : Audited>(&mut self, index : usize) -> T
+    {
+        let entity = T::get_list_mut(self).remove(index);
+        self.touch(T::KIND, entity.get_id(), Some(entity.get_name()));
+        self.record(Revert::Insert(index, entity.clone().into_entity()));
+        return entity;
+    }
+
+    /// The entity to change, its version before the command is recorded the first time
+    fn change_entity<T : Audited>(&mut self, index : usize) -> &mut T
+    {
+        let entity = &T::get_list(self)[index];
+        let (id, name) = (entity.get_id(), entity.get_name().to_string());
+        self.touch(T::KIND, id, Some(&name));
+
+        if self.pending.as_mut().is_some_and(|p| p.saved.insert((T::KIND, id)))
+        {
+            let entity = T::get_list(self)[index].clone();
+            self.record(Revert::Replace(index, entity.into_entity()));
+        }
+
+        return &mut T::get_list_mut(self)[index];
+    }
+
+    /// The entity to give the new id, e.g. because another one has the same id. It is logged as
+    /// changed under the new id, the entity which keeps the old id stays as it is.
+    fn renumber_entity<T : Audited>(&mut self, index : usize, id : IdType) -> &mut T
+    {
+        let name = T::get_list(self)[index].get_name().to_string();
+        self.touch(T::KIND, id, Some(&name));
+
+        if self.pending.as_mut().is_some_and(|p| p.saved.insert((T::KIND, id)))
+        {
+            let entity = T::get_list(self)[index].clone();
+            self.record(Revert::Replace(index, entity.into_entity()));
+        }
+
+        return &mut T::get_list_mut(self)[index];
+    }
+
+    fn replace_entity<T : Audited>(&mut self, index : usize, entity : T)
+    {
+        let id = entity.get_id();
+
+        if id != T::get_list(self)[index].get_id()
+        {
+            let before = T::get_list(self).iter().find(|e| e.get_id() == id).map(|e| e.get_name().to_string());
+            self.touch(T::KIND, id, before.as_deref());
+        }
+
+        *self.change_entity::<T>(index) = entity;
+    }
+
+    fn apply(&mut self, revert : Revert)
+    {
+        match revert
+        {
+            Revert::Insert(index, entity) => match entity
+            {
+                Entity::Compartment(comp) => self.insert_entity(index, comp),
+                Entity::Container(cont)   => self.insert_entity(index, cont),
+                Entity::Item(item)        => self.insert_entity(index, item),
+                Entity::Tag(tag)          => self.insert_entity(index, tag)
+            },
+            Revert::Remove(kind, index) => match kind
+            {
+                EntityKind::Compartment => {self.remove_entity::<Compartment>(index);}
+                EntityKind::Container   => {self.remove_entity::<Container>(index);}
+                EntityKind::Item        => {self.remove_entity::<Item>(index);}
+                EntityKind::Tag         => {self.remove_entity::<Tag>(index);}
+            },
+            Revert::Replace(index, entity) => match entity
+            {
+                Entity::Compartment(comp) => self.replace_entity(index, comp),
+                Entity::Container(cont)   => self.replace_entity(index, cont),
+                Entity::Item(item)        => self.replace_entity(index, item),
+                Entity::Tag(tag)          => self.replace_entity(index, tag)
+            },
+            Revert::Counters(counters) => self.set_counters(counters)
+        }
+    }
+
+    /// Replaces the list of one kind as a whole, only the entities which differ are recorded
+    fn replace_list<T : Audited>(&mut self, list : Vec<T>)
+    {
+        let old = mem::replace(T::get_list_mut(self), list);
+        self.record_list(old);
+    }
+
+    /// Records the changes from the old list to the current one, for the changes which are not made entity by entity
+    fn record_list<T : Audited>(&mut self, old : Vec<T>)
+    {
+        if self.pending.is_none() {return;}
+
+        let new = T::get_list(self);
+        let mut reverts = Vec::new();
+        let mut inserts = Vec::new();
+        let mut touched = Vec::new();
+
+        for (index, entity) in old.iter().enumerate()
+        {
+            match new.get(index)
+            {
+                Some(current) if current == entity => {}
+                Some(_) => reverts.push(Revert::Replace(index, entity.clone().into_entity())),
+                None    => inserts.push(Revert::Insert(index, entity.clone().into_entity()))
+            }
+        }
+
+        // the entities are put back from the first to the last one, the reverts are applied in reverse
+        reverts.extend(inserts.into_iter().rev());
+        reverts.extend((old.len()..new.len()).map(|index| Revert::Remove(T::KIND, index)));
+
+        // an id stands for the first entity with it, as find_index looks it up
+        let mut old_by_id : HashMap<IdType, &T> = HashMap::new();
+        for entity in old.iter() {old_by_id.entry(entity.get_id()).or_insert(entity);}
+        let new_ids : HashSet<IdType> = new.iter().map(|e| e.get_id()).collect();
+
+        for (index, entity) in new.iter().enumerate()
+        {
+            // an entity which is still at its index has no previous version recorded, it is not stamped
+            if old.get(index) == Some(entity) {continue;}
+
+            match old_by_id.get(&entity.get_id())
+            {
+                Some(before) if *before == entity => {}
+                Some(before) => touched.push((entity.get_id(), Some(before.get_name().to_string()))),
+                None => touched.push((entity.get_id(), None))
+            }
+        }
+
+        for entity in old.iter().filter(|e| !new_ids.contains(&e.get_id()))
+        {
+            touched.push((entity.get_id(), Some(entity.get_name().to_string())));
+        }
+
+        for (id, before) in touched {self.touch(T::KIND, id, before.as_deref());}
+        for revert in reverts {self.record(revert);}
+    }
+}
+
+/* ****************************+ integrity check ****************************+ */
+
+pub static LOST_AND_FOUND: &str = "lost+found";
+
+fn duplicate_ids(ids : impl Iterator<Item = IdType>) -> Vec<IdType>
+{
+    let mut seen = HashSet::new();
+    let mut dups = Vec::new();
+
+    for id in ids
+    {
+        if !seen.insert(id) && !dups.contains(&id) {dups.push(id);}
+    }
+
+    return dups;
 }
 
-//trait IdObject{fn get_id(&self) -> u32; }
-//impl IdObject for Tag        { fn get_id(&self) -> IdType {return self.id;} }
-//impl IdObject for Item       { fn get_id(&self) -> IdType {return self.id;} }
-//impl IdObject for Container  { fn get_id(&self) -> IdType {return self.id;} }
-//impl IdObject for Compartment{ fn get_id(&self) -> IdType {return self.id;} }
+/// The indices of the entities whose id was used by an earlier one already
+fn duplicate_indices(ids : impl Iterator<Item = IdType>) -> Vec<usize>
+{
+    let mut seen = HashSet::new();
+    return ids.enumerate().filter(|(_, id)| !seen.insert(*id)).map(|(index, _)| index).collect();
+}
 
-pub struct SearchResult <'a>
+/// The tags which exist, each one only once
+fn valid_tags(tags : &[IdType], tag_ids : &HashSet<IdType>) -> Vec<IdType>
 {
-    compartments    : Vec<&'a Compartment>,
-    containers      : Vec<&'a Container>,
-    tags            : Vec<&'a Tag>,
-    items           : Vec<&'a Item>,
+    let mut seen = HashSet::new();
+    return tags.iter().copied().filter(|t| tag_ids.contains(t) && seen.insert(*t)).collect();
 }
 
-pub fn search<'a>(key_word : &str, inv :&'a Inventory) -> SearchResult <'a>
+fn next_counter(counter : IdType, ids : impl Iterator<Item = IdType>) -> IdType
 {
-    return SearchResult 
-    {
-        compartments    : vec!(&inv.compartments[0]),
-        containers      : Vec::new(),
-        tags            : Vec::new(),
-        items           : Vec::new()
-    }
+    return ids.map(|id| id + 1).fold(counter, IdType::max);
 }
 
 impl Inventory
 {
-    pub fn check_tags_ids(&self, ids : &Vec<IdType>) -> Result<(),String>
+    /// Validates every cross reference of the inventory, returns a description of each problem found
+    pub fn check(&self) -> Vec<String>
     {
-        'main_loop : for id in ids
+        let mut problems = Vec::new();
+
+        for id in duplicate_ids(self.compartments.iter().map(|c| c.id)) {problems.push(format!("duplicate compartment id {:04X}", id));}
+        for id in duplicate_ids(self.containers.iter().map(|c| c.id))   {problems.push(format!("duplicate container id {:04X}", id));}
+        for id in duplicate_ids(self.items.iter().map(|i| i.id))        {problems.push(format!("duplicate item id {:04X}", id));}
+        for id in duplicate_ids(self.tags.iter().map(|t| t.id))         {problems.push(format!("duplicate tag id {:04X}", id));}
+
+        let counters = [("compartment", self.cnt_compartment, next_counter(0, self.compartments.iter().map(|c| c.id))),
+                        ("container",   self.cnt_container,   next_counter(0, self.containers.iter().map(|c| c.id))),
+                        ("item",        self.cnt_item,        next_counter(0, self.items.iter().map(|i| i.id))),
+                        ("tag",         self.cnt_tag,         next_counter(0, self.tags.iter().map(|t| t.id)))];
+
+        for (kind, counter, min) in counters.iter()
         {
-            for tag in self.tags.iter()
+            if counter < min {problems.push(format!("{} counter {:04X} is lower than the next free id {:04X}", kind, counter, min));}
+        }
+
+        for comp in self.compartments.iter()
+        {
+            for cont_id in comp.containers.iter()
             {
-                if &tag.id == id 
+                match self.get_container(*cont_id)
                 {
-                    continue 'main_loop;
+                    None => problems.push(format!("compartment {:04X} lists the missing container {:04X}", comp.id, cont_id)),
+                    Some(cont) if cont.id_comp != comp.id => problems.push(format!("compartment {:04X} lists container {:04X} which belongs to compartment {:04X}", comp.id, cont_id, cont.id_comp)),
+                    _ => {}
                 }
             }
-            return Err(format!("{}",id));
         }
-        return Ok(());
-    }
 
-    pub fn add_tag(&mut self, name : &str)
-    {
-        self.tags.push
-        (
-            Tag
+        for cont in self.containers.iter()
+        {
+            match self.get_compartment(cont.id_comp)
             {
-                name  : String::from(name),
-                id : self.cnt_tag
+                None => problems.push(format!("container {:04X} is orphaned, its compartment {:04X} does not exist", cont.id, cont.id_comp)),
+                Some(comp) if !comp.containers.contains(&cont.id) => problems.push(format!("container {:04X} is not listed in its compartment {:04X}", cont.id, comp.id)),
+                _ => {}
             }
-        );
-        self.cnt_tag += 1;
-    }
 
-    pub fn add_item(&mut self, name : &str, con_id : IdType) -> Result<(),String>
-    {
-        let cont_index = con_id as usize;
-        if let None = self.containers.get(cont_index)
+            for item_id in cont.items.iter()
+            {
+                match self.items.iter().find(|i| i.id == *item_id)
+                {
+                    None => problems.push(format!("container {:04X} lists the missing item {:04X}", cont.id, item_id)),
+                    Some(item) if item.id_cont != cont.id => problems.push(format!("container {:04X} lists item {:04X} which belongs to container {:04X}", cont.id, item_id, item.id_cont)),
+                    _ => {}
+                }
+            }
+
+            for tag_id in cont.tags.iter()
+            {
+                if self.tags.iter().all(|t| t.id != *tag_id) {problems.push(format!("container {:04X} uses the missing tag {:04X}", cont.id, tag_id));}
+            }
+        }
+
+        for comp in self.compartments.iter()
         {
-            return Err(format!("The container with the id: {} was not found!!!", con_id));
+            for tag_id in comp.tags.iter()
+            {
+                if self.tags.iter().all(|t| t.id != *tag_id) {problems.push(format!("compartment {:04X} uses the missing tag {:04X}", comp.id, tag_id));}
+            }
         }
 
-        self.items.push
-        (
-            Item
+        for item in self.items.iter()
+        {
+            for tag_id in item.tags.iter()
             {
-                name  : String::from(name),
-                id : self.cnt_item,
-                id_cont : con_id,
+                if self.tags.iter().all(|t| t.id != *tag_id) {problems.push(format!("item {:04X} uses the missing tag {:04X}", item.id, tag_id));}
             }
-        );
 
-        self.containers[cont_index].items.push(self.cnt_item);
-        self.cnt_item += 1;
+            match self.get_container(item.id_cont)
+            {
+                None => problems.push(format!("item {:04X} is orphaned, its container {:04X} does not exist", item.id, item.id_cont)),
+                Some(cont) if !cont.items.contains(&item.id) => problems.push(format!("item {:04X} is not listed in its container {:04X}", item.id, cont.id)),
+                _ => {}
+            }
+        }
 
-        return Ok(());
+        return problems;
     }
 
-    pub fn add_container(&mut self, name : &str, com_id : IdType, tags : Vec<IdType>) -> Result<(),String>
+    /// Repairs the problems reported by `check`. Duplicate ids are reassigned, the parent lists
+    /// are rebuilt from the children and orphans are moved into a "lost+found" compartment/container.
+    pub fn repair(&mut self)
     {
-        let comp_index = com_id as usize;
+        self.set_counters([next_counter(self.cnt_compartment, self.compartments.iter().map(|c| c.id)),
+                           next_counter(self.cnt_container,   self.containers.iter().map(|c| c.id)),
+                           next_counter(self.cnt_item,        self.items.iter().map(|i| i.id)),
+                           next_counter(self.cnt_tag,         self.tags.iter().map(|t| t.id))]);
+
+        for index in duplicate_indices(self.compartments.iter().map(|c| c.id))
+        {
+            let id = self.cnt_compartment;
+            self.save_counters();
+            self.renumber_entity::<Compartment>(index, id).id = id;
+            self.cnt_compartment += 1;
+        }
+
+        for index in duplicate_indices(self.containers.iter().map(|c| c.id))
+        {
+            let id = self.cnt_container;
+            self.save_counters();
+            self.renumber_entity::<Container>(index, id).id = id;
+            self.cnt_container += 1;
+        }
 
-        match self.check_tags_ids(&tags) {Err(e) => {return Err(format!("Check the tag ids, {} was not found!!!",e));} _=> {}}
+        for index in duplicate_indices(self.items.iter().map(|i| i.id))
+        {
+            let id = self.cnt_item;
+            self.save_counters();
+            self.renumber_entity::<Item>(index, id).id = id;
+            self.cnt_item += 1;
+        }
 
-        if let None = self.compartments.get(comp_index)
+        for index in duplicate_indices(self.tags.iter().map(|t| t.id))
         {
-            return Err(format!("The compartment with the id: {} was not found!!!", com_id));
+            let id = self.cnt_tag;
+            self.save_counters();
+            self.renumber_entity::<Tag>(index, id).id = id;
+            self.cnt_tag += 1;
         }
 
-        let cont_id = self.compartments[comp_index].containers.len() as IdType;
+        // an item listed by exactly one container belongs to it, older files used the vector
+        // index of the container as id_cont, so the container lists are the better source
+        let mut listed_in : HashMap<IdType, Vec<IdType>> = HashMap::new();
+        for cont in self.containers.iter()
+        {
+            for item_id in cont.items.iter() {listed_in.entry(*item_id).or_default().push(cont.id);}
+        }
 
-        self.containers.push
-        (
-            Container
+        let cont_ids : HashSet<IdType> = self.containers.iter().map(|c| c.id).collect();
+        let mut lost_items = Vec::new();
+        for index in 0..self.items.len()
+        {
+            let item = &self.items[index];
+            match listed_in.get(&item.id)
             {
-                name  : String::from(name),
-                id : cont_id,
-                id_comp : com_id,
-                items : Vec::new(),
-                tags : Vec::from(tags)
+                Some(conts) if conts.len() == 1 => if item.id_cont != conts[0] {self.change_entity::<Item>(index).id_cont = conts[0];},
+                _ => if !cont_ids.contains(&item.id_cont) {lost_items.push(index);}
             }
-        );
+        }
 
-        self.compartments[comp_index].containers.push(cont_id);
+        let comp_ids : HashSet<IdType> = self.compartments.iter().map(|c| c.id).collect();
+        let lost_conts : Vec<usize> = (0..self.containers.len()).filter(|index| !comp_ids.contains(&self.containers[*index].id_comp)).collect();
 
-        return Ok(());
+        if !lost_items.is_empty() || !lost_conts.is_empty()
+        {
+            let comp_id = self.lost_and_found_compartment();
+            for index in lost_conts {self.change_entity::<Container>(index).id_comp = comp_id;}
+
+            if !lost_items.is_empty()
+            {
+                let cont_id = self.lost_and_found_container(comp_id);
+                for index in lost_items {self.change_entity::<Item>(index).id_cont = cont_id;}
+            }
+        }
+
+        for index in 0..self.compartments.len()
+        {
+            let comp = &self.compartments[index];
+            let containers : Vec<IdType> = self.containers.iter().filter(|c| c.id_comp == comp.id).map(|c| c.id).collect();
+            if comp.containers != containers {self.change_entity::<Compartment>(index).containers = containers;}
+        }
+
+        for index in 0..self.containers.len()
+        {
+            let cont = &self.containers[index];
+            let items : Vec<IdType> = self.items.iter().filter(|i| i.id_cont == cont.id).map(|i| i.id).collect();
+            if cont.items != items {self.change_entity::<Container>(index).items = items;}
+        }
+
+        let tag_ids : HashSet<IdType> = self.tags.iter().map(|t| t.id).collect();
+
+        for index in 0..self.compartments.len()
+        {
+            let tags = valid_tags(&self.compartments[index].tags, &tag_ids);
+            if self.compartments[index].tags != tags {self.change_entity::<Compartment>(index).tags = tags;}
+        }
+
+        for index in 0..self.containers.len()
+        {
+            let tags = valid_tags(&self.containers[index].tags, &tag_ids);
+            if self.containers[index].tags != tags {self.change_entity::<Container>(index).tags = tags;}
+        }
+
+        for index in 0..self.items.len()
+        {
+            let tags = valid_tags(&self.items[index].tags, &tag_ids);
+            if self.items[index].tags != tags {self.change_entity::<Item>(index).tags = tags;}
+        }
     }
 
-    pub fn add_compartment(&mut self, name : &str)
+    fn lost_and_found_compartment(&mut self) -> IdType
+    {
+        if let Some(comp) = self.compartments.iter().find(|c| c.name == LOST_AND_FOUND)
+        {
+            return comp.id;
+        }
+
+        let id = self.cnt_compartment;
+        self.add_compartment(LOST_AND_FOUND);
+        return id;
+    }
+
+    fn lost_and_found_container(&mut self, comp_id : IdType) -> IdType
     {
-        self.compartments.push
+        if let Some(cont) = self.containers.iter().find(|c| c.name == LOST_AND_FOUND && c.id_comp == comp_id)
+        {
+            return cont.id;
+        }
+
+        let id = self.cnt_container;
+        self.save_counters();
+        self.push_entity
         (
-            Compartment
+            Container
             {
-                name  : String::from(name),
-                id : self.cnt_compartment,
-                containers : Vec::new()
+                name  : String::from(LOST_AND_FOUND),
+                id,
+                id_comp : comp_id,
+                items : Vec::new(),
+                tags : Vec::new(),
+                audit : Audit::default()
             }
         );
-        self.cnt_compartment += 1;
+        self.cnt_container += 1;
+        return id;
     }
 }
 
 
 
-pub fn save_inventory(inventory : &Inventory) -> Result<(), Box<dyn Error>> 
+pub fn save_inventory(inventory : &Inventory, file_name : &str) -> Result<(), Box<dyn Error>> 
 {
     let serialized = serde_json::to_string(&inventory)?;
-    fs::write(get_file_location(FILE_NAME), serialized)?;
+    write_atomic(file_name, serialized.as_bytes())?;
+    return storage::backup_after_save(file_name);
+}
+
+fn io_error(action : &str, path : &Path, err : io::Error) -> Box<dyn Error>
+{
+    return Box::new(GenericError::new(format!("{} {} failed: {}", action, path.display(), err)));
+}
+
+/// Writes the file crash-safe: the data goes to "<file>.tmp" which is synced to disk and then
+/// renamed over the original, the previous version of the file is kept as "<file>.bak"
+pub fn write_atomic(file_name : &str, data : &[u8]) -> Result<(), Box<dyn Error>>
+{
+    let path     = Path::new(file_name);
+    let tmp_path = PathBuf::from(format!("{}.tmp", file_name));
+    let bak_path = PathBuf::from(format!("{}.bak", file_name));
+
+    // the temporary file replaces the original, so it takes over its permissions
+    let written = fs::File::create(&tmp_path)
+        .and_then(|mut file|
+        {
+            if let Ok(metadata) = fs::metadata(path) {file.set_permissions(metadata.permissions())?;}
+            file.write_all(data)?;
+            file.sync_all()
+        });
+
+    if let Err(e) = written
+    {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(io_error("Writing the temporary file", &tmp_path, e));
+    }
+
+    if path.exists()
+    {
+        if let Err(e) = fs::copy(path, &bak_path)
+        {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(io_error("Creating the backup", &bak_path, e));
+        }
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path)
+    {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(io_error("Replacing", path, e));
+    }
+
+    // sync the directory as well so the rename itself survives a crash
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty())
+    {
+        if let Ok(dir) = fs::File::open(dir) {let _ = dir.sync_all();}
+    }
+
     return Ok(());
 }
 
+fn get_env_dir(name : &str) -> Option<PathBuf>
+{
+    return env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+}
+
+/// The directory holding the inventory: $INVI_HOME, $XDG_DATA_HOME/invi, $XDG_CONFIG_HOME/invi
+/// or $HOME/.config/invi, the first one which is set wins
+pub fn get_data_dir() -> PathBuf
+{
+    if let Some(dir) = get_env_dir("INVI_HOME")       {return dir;}
+    if let Some(dir) = get_env_dir("XDG_DATA_HOME")   {return dir.join("invi");}
+    if let Some(dir) = get_env_dir("XDG_CONFIG_HOME") {return dir.join("invi");}
+    if let Some(dir) = get_env_dir("HOME")            {return dir.join(".config").join("invi");}
+    return get_legacy_dir();
+}
+
+/// The location used by older versions of invi, it was hardcoded to /home/<user>
+fn get_legacy_dir() -> PathBuf
+{
+    return PathBuf::from(format!("/home/{}/.config/invi", whoami::username()));
+}
+
 pub fn get_file_location(file_name : &str) -> String
 {
-    let user_name = &whoami::username();
-    let file_path = format!("/home/{}/.config/invi/{}",user_name,file_name);
-    return file_path;
+    return get_data_dir().join(file_name).to_string_lossy().to_string();
+}
+
+/// Copies the inventory from the legacy location to the data directory, if there is none yet.
+/// The old file is left in place. With INVI_HOME set the data directory was chosen explicitly,
+/// e.g. for a test, so nothing is copied then. Returns the old location if the file was copied.
+pub fn migrate_legacy_file() -> Result<Option<PathBuf>, Box<dyn Error>>
+{
+    if get_env_dir("INVI_HOME").is_some() {return Ok(None);}
+
+    let old_file = get_legacy_dir().join(FILE_NAME);
+    let new_file = get_data_dir().join(FILE_NAME);
+
+    if new_file.exists() || !old_file.exists() || old_file == new_file {return Ok(None);}
+
+    fs::create_dir_all(get_data_dir())?;
+    fs::copy(&old_file, &new_file)?;
+
+    return Ok(Some(old_file));
 }
 
-pub fn load_inventory_from_home() -> Result<Inventory, Box<dyn Error>> 
+/// The inventory in the data directory, a blank one is created if there is none yet
+pub fn get_default_file() -> Result<String, Box<dyn Error>> 
 {
     let file_name = get_file_location(FILE_NAME);
 
     if Path::new(&file_name).exists()
     {
-        return Ok(load_inventory(get_file_location(FILE_NAME))?);
+        return Ok(file_name);
     }
 
     let file_dir = get_file_location("");
 
-    fs::create_dir_all(Path::new(&file_dir)).expect(&format!("The default file could not be found and creating the directory {} failed", file_dir));
+    if let Err(e) = fs::create_dir_all(Path::new(&file_dir))
+    {
+        return Err(Box::new(GenericError::new(format!("The default file could not be found and creating the directory {} failed: {}", file_dir, e))));
+    }
 
-    new_inventory(file_name.clone()).expect(&format!("Cloud not create new blank inventory database: {}",file_name));
+    if let Err(e) = new_inventory(file_name.clone())
+    {
+        return Err(Box::new(GenericError::new(format!("Cloud not create new blank inventory database {}: {}", file_name, e))));
+    }
 
-    return Ok(load_inventory(get_file_location(FILE_NAME))?);
+    return Ok(file_name);
 }
 
-
-
 pub fn load_inventory(file_name : String) -> Result<Inventory, Box<dyn Error>> 
 {
     let json_in = fs::read_to_string(file_name)?;
-    let obj : Inventory = serde_json::from_str(&json_in)?;
+    return from_document(serde_json::from_str(&json_in)?);
+}
+
+/// Builds the inventory from a parsed document, older format versions are upgraded first
+pub fn from_document(mut doc : serde_json::Value) -> Result<Inventory, Box<dyn Error>> 
+{
+    let from = migrate::migrate(&mut doc)?;
+    let mut obj : Inventory = serde_json::from_value(doc)?;
+    migrate::upgrade_inventory(&mut obj, from);
     return Ok(obj);
 }
 
 pub fn new_inventory(file_name : String) -> Result<(), Box<dyn Error>> 
 {
-    let new_inventory = Inventory
-    {
-        compartments    : Vec::new(),
-        containers      : Vec::new(),
-        tags            : Vec::new(),
-        items           : Vec::new(),
-        cnt_compartment : 0 as IdType,
-        cnt_container   : 0 as IdType,
-        cnt_item        : 0 as IdType,
-        cnt_tag         : 0 as IdType,
-    };
+    let new_inventory = Inventory::new();
 
     let serialized = serde_json::to_string(&new_inventory)?;
-    fs::write(&file_name, serialized)?;
+    write_atomic(&file_name, serialized.as_bytes())?;
     return Ok(());
 }
 
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::json;
+
+    use super::*;
+
+    /// A file of format version 0, the containers were numbered per compartment and the items
+    /// used the index of their container as id_cont
+    fn legacy_document() -> serde_json::Value
+    {
+        return json!(
+        {
+            "compartments" :
+            [
+                {"name" : "Shelf",  "id" : 0, "containers" : [0]},
+                {"name" : "Drawer", "id" : 1, "containers" : [0]}
+            ],
+            "containers" :
+            [
+                {"name" : "Box",  "id" : 0, "id_comp" : 0, "items" : [0], "tags" : []},
+                {"name" : "Tray", "id" : 0, "id_comp" : 1, "items" : [1], "tags" : []}
+            ],
+            "items" :
+            [
+                {"name" : "M3 screw", "id" : 0, "id_cont" : 0},
+                {"name" : "M3 nut",   "id" : 1, "id_cont" : 1}
+            ],
+            "tags" : [],
+            "cnt_compartment" : 2,
+            "cnt_container"   : 1,
+            "cnt_item"        : 2,
+            "cnt_tag"         : 0
+        });
+    }
+
+    #[test]
+    fn upgrades_version_0_with_duplicate_container_ids()
+    {
+        let inventory = from_document(legacy_document()).unwrap();
+
+        assert_eq!(inventory.version, migrate::FORMAT_VERSION);
+        assert!(inventory.check().is_empty(), "{:?}", inventory.check());
+
+        let ids : Vec<IdType> = inventory.containers.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert_eq!(inventory.cnt_container, 2);
+
+        assert_eq!(inventory.compartments[1].containers, vec![1]);
+        assert_eq!(inventory.items[1].id_cont, 1);
+        assert_eq!(inventory.items[0].quantity, 1);
+    }
+
+    #[test]
+    fn repairs_a_broken_inventory()
+    {
+        let mut inventory = Inventory::new();
+        inventory.add_compartment("Shelf");
+        inventory.add_container("Box", 0, Vec::new()).unwrap();
+        inventory.add_item("M3 screw", 0, 100, "pcs").unwrap();
+        inventory.add_item("M3 nut", 0, 100, "pcs").unwrap();
+
+        // a duplicate item id, an orphaned item, a missing listed item and a counter which is too low
+        inventory.items[1].id = 0;
+        inventory.items.push(Item{name : String::from("Washer"), id : 7, id_cont : 5, quantity : 1, unit : String::new(),
+                                  min_quantity : 0, tags : Vec::new(), audit : Audit::default()});
+        inventory.containers[0].items.push(9);
+        inventory.cnt_item = 1;
+        assert_eq!(inventory.check().len(), 5, "{:?}", inventory.check());
+
+        inventory.repair();
+        assert!(inventory.check().is_empty(), "{:?}", inventory.check());
+
+        let ids : Vec<IdType> = inventory.items.iter().map(|i| i.id).collect();
+        assert_eq!(ids, vec![0, 8, 7]);
+        assert_eq!(inventory.cnt_item, 9);
+
+        let lost = inventory.get_container(inventory.items[2].id_cont).unwrap();
+        assert_eq!(lost.name, LOST_AND_FOUND);
+        assert_eq!(inventory.get_compartment(lost.id_comp).unwrap().name, LOST_AND_FOUND);
+    }
+
+    #[test]
+    fn reverts_a_repair()
+    {
+        let mut inventory = from_document(json!({"compartments" : [], "containers" : [], "items" : [], "tags" : [],
+                                                 "cnt_compartment" : 0, "cnt_container" : 0, "cnt_item" : 0, "cnt_tag" : 0})).unwrap();
+        inventory.add_compartment("Shelf");
+        inventory.add_compartment("Drawer");
+        inventory.compartments[1].id = 0;
+        let before = serde_json::to_value(&inventory).unwrap();
+
+        inventory.begin();
+        inventory.repair();
+        let reverts = inventory.commit(":fsck!");
+        assert!(inventory.check().is_empty());
+
+        // only the renumbered compartment is stamped and logged
+        let logged : Vec<(IdType, &str)> = inventory.log.iter().map(|e| (e.id, e.action.as_str())).collect();
+        assert_eq!(logged, vec![(2, "changed")]);
+        assert!(inventory.compartments[0].audit.modified_at.is_empty());
+
+        inventory.revert(reverts);
+        inventory.take_reverts();
+        let mut after = serde_json::to_value(&inventory).unwrap();
+        after["log"] = before["log"].clone();
+        assert_eq!(after, before);
+    }
+
+    /// Tags ESD (0) and Metric (1), the drawer holds an ESD bag and the untagged "ESD foam"
+    fn search_inventory() -> Inventory
+    {
+        let mut inventory = Inventory::new();
+        inventory.add_tag("ESD");
+        inventory.add_tag("Metric");
+        inventory.add_compartment("Shelf");
+        inventory.add_compartment("Drawer");
+        inventory.add_container("Screw box", 0, vec![1]).unwrap();
+        inventory.add_container("Bag", 1, vec![0]).unwrap();
+        inventory.add_container("ESD foam", 1, Vec::new()).unwrap();
+        inventory.add_item("M3 screw", 0, 1, "").unwrap();
+        inventory.add_item("Zählerchip", 1, 1, "").unwrap();
+        inventory.tag_entity(EntityKind::Item, 1, &[0]).unwrap();
+        return inventory;
+    }
+
+    fn names<T : Audited>(entities : &[&T]) -> Vec<String>
+    {
+        return entities.iter().map(|e| e.get_name().to_string()).collect();
+    }
+
+    #[test]
+    fn searches_the_names_case_insensitive()
+    {
+        let inventory = search_inventory();
+        let result = search("SCREW", &inventory);
+
+        assert!(result.compartments.is_empty() && result.tags.is_empty());
+        assert_eq!(names(&result.containers), vec!["Screw box"]);
+        assert_eq!(names(&result.items), vec!["M3 screw"]);
+
+        assert_eq!(names(&search("zähler", &inventory).items), vec!["Zählerchip"]);
+        assert!(search("washer", &inventory).is_empty());
+    }
+
+    #[test]
+    fn pulls_in_the_tagged_entities()
+    {
+        let inventory = search_inventory();
+
+        let result = search("metric", &inventory);
+        assert_eq!(names(&result.tags), vec!["Metric"]);
+        assert_eq!(names(&result.containers), vec!["Screw box"]);
+        assert!(result.items.is_empty());
+
+        // without # the names match as well, with # only the tags do
+        let result = search("esd", &inventory);
+        assert_eq!(names(&result.containers), vec!["Bag", "ESD foam"]);
+        assert_eq!(names(&result.items), vec!["Zählerchip"]);
+
+        let result = search("#esd", &inventory);
+        assert_eq!(names(&result.tags), vec!["ESD"]);
+        assert_eq!(names(&result.containers), vec!["Bag"]);
+        assert_eq!(names(&result.items), vec!["Zählerchip"]);
+    }
+
+    #[test]
+    fn matches_byte_ranges_of_non_ascii_names()
+    {
+        assert_eq!(match_range("M3 Screw", "screw"), Some((3, 8)));
+        assert_eq!(match_range("Zählerchip", "ÄHLER"), Some((1, 7)));
+        assert_eq!(match_range("Zählerchip", "chip"), Some((7, 11)));
+        assert_eq!(match_range("Zählerchip", "  "), None);
+        assert_eq!(match_range("Zählerchip", "zahler"), None);
+        assert_eq!(match_range("GRÖSSE", "össe"), Some((2, 7)));
+    }
+}
@@ -0,0 +1,77 @@
+use std::error::Error;
+use std::io::Write;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::time::Duration;
+use std::env;
+
+use chrono::{SecondsFormat, Utc};
+
+/// The recovery journal of an inventory, every applied command which changed the inventory since
+/// the last write is appended to it. It is removed on every write, so if it is still there on
+/// startup invi was not closed properly.
+pub fn get_journal_file(file_name : &str) -> String
+{
+    return format!("{}.journal", file_name);
+}
+
+pub fn append(file_name : &str, command : &str) -> Result<(), Box<dyn Error>>
+{
+    let mut file = OpenOptions::new().create(true).append(true).open(get_journal_file(file_name))?;
+    writeln!(file, "{}\t{}", Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), command.trim())?;
+    file.sync_data()?;
+    return Ok(());
+}
+
+pub fn clear(file_name : &str) -> Result<(), Box<dyn Error>>
+{
+    let journal = get_journal_file(file_name);
+    if Path::new(&journal).exists() {fs::remove_file(journal)?;}
+    return Ok(());
+}
+
+/// Moves the journal to "<file>.journal.old", replacing an older one, and returns the new name
+pub fn set_aside(file_name : &str) -> Result<String, Box<dyn Error>>
+{
+    let old = format!("{}.old", get_journal_file(file_name));
+    fs::rename(get_journal_file(file_name), &old)?;
+    return Ok(old);
+}
+
+/// The commands in the journal, without their timestamps
+pub fn read(file_name : &str) -> Result<Vec<String>, Box<dyn Error>>
+{
+    let journal = get_journal_file(file_name);
+    if !Path::new(&journal).exists() {return Ok(Vec::new());}
+
+    let commands = fs::read_to_string(journal)?.lines()
+        .filter_map(|line| line.split_once('\t').map(|(_, command)| command.to_string()))
+        .filter(|command| !command.is_empty())
+        .collect();
+
+    return Ok(commands);
+}
+
+/// True if there is a journal which was written after the inventory file was last modified
+pub fn is_newer(file_name : &str) -> bool
+{
+    let journal = fs::metadata(get_journal_file(file_name)).and_then(|m| m.modified());
+    let file = fs::metadata(file_name).and_then(|m| m.modified());
+
+    return match (journal, file)
+    {
+        (Ok(journal), Ok(file)) => journal >= file,
+        (Ok(_), Err(_)) => true,
+        _ => false
+    };
+}
+
+/// The autosave interval configured with INVI_AUTOSAVE in seconds, None (the default) or 0 disables it
+pub fn get_autosave_interval() -> Option<Duration>
+{
+    return match env::var("INVI_AUTOSAVE").ok().and_then(|value| value.trim().parse::<u64>().ok())
+    {
+        Some(0) | None => None,
+        Some(secs) => Some(Duration::from_secs(secs))
+    };
+}
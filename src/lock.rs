@@ -0,0 +1,116 @@
+use std::error::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::time::SystemTime;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Read;
+
+static SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+const SQLITE_HEADER_LEN: usize = 100;
+
+/// An advisory lock on "<file>.lock", it is released when dropped. The lock file itself is kept,
+/// the inventory file can not be locked directly since saving replaces it.
+pub struct FileLock
+{
+    _file : File
+}
+
+/// Takes the lock for the inventory file, None if another invi session holds it already
+pub fn try_lock(file_name : &str) -> Result<Option<FileLock>, Box<dyn Error>>
+{
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(format!("{}.lock", file_name))?;
+
+    return match file.try_lock()
+    {
+        Ok(()) => Ok(Some(FileLock{_file : file})),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(Box::new(e))
+    };
+}
+
+/// Identifies the content of a file on disk, used to notice changes made by someone else
+#[derive(Clone, Debug)]
+pub struct FileStamp
+{
+    modified : Option<SystemTime>,
+    len      : u64,
+    hash     : u64
+}
+
+/// Stamps are equal if the content is, a touch or a checkout of the same content is no change.
+/// The modification time is only used by is_unchanged to skip reading the file.
+impl PartialEq for FileStamp
+{
+    fn eq(&self, other : &FileStamp) -> bool
+    {
+        return self.len == other.len && self.hash == other.hash;
+    }
+}
+
+/// The bytes identifying the content of the file. A SQLite database counts its commits in its
+/// 100 byte header (invi does not use the WAL mode, which would not), so only the header is read.
+fn read_content(file_name : &str) -> Result<Vec<u8>, Box<dyn Error>>
+{
+    let mut header = Vec::with_capacity(SQLITE_HEADER_LEN);
+    File::open(file_name)?.take(SQLITE_HEADER_LEN as u64).read_to_end(&mut header)?;
+
+    if header.len() == SQLITE_HEADER_LEN && header.starts_with(SQLITE_MAGIC) {return Ok(header);}
+    return Ok(fs::read(file_name)?);
+}
+
+/// The stamp of the file as it is on disk now, None if it does not exist
+pub fn get_stamp(file_name : &str) -> Result<Option<FileStamp>, Box<dyn Error>>
+{
+    let meta = match fs::metadata(file_name)
+    {
+        Ok(meta) => meta,
+        Err(_) => return Ok(None)
+    };
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&read_content(file_name)?);
+
+    return Ok(Some(FileStamp{modified : meta.modified().ok(), len : meta.len(), hash : hasher.finish()}));
+}
+
+/// Cheap check of the modification time and size only, used before hashing the whole file
+pub fn is_unchanged(file_name : &str, stamp : &Option<FileStamp>) -> bool
+{
+    return match (fs::metadata(file_name), stamp)
+    {
+        (Ok(meta), Some(stamp)) => meta.modified().ok() == stamp.modified && meta.len() == stamp.len,
+        (Err(_), None) => true,
+        _ => false
+    };
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn compares_the_content()
+    {
+        let file_name = env::temp_dir().join(format!("invi-test-stamp-{}.json", process::id())).to_string_lossy().to_string();
+        fs::write(&file_name, "{}").unwrap();
+        let stamp = get_stamp(&file_name).unwrap();
+
+        // a touch changes the modification time only
+        let later = SystemTime::now() + Duration::from_secs(60);
+        File::options().write(true).open(&file_name).unwrap().set_modified(later).unwrap();
+        assert!(!is_unchanged(&file_name, &stamp));
+        assert_eq!(get_stamp(&file_name).unwrap(), stamp);
+
+        fs::write(&file_name, "[]").unwrap();
+        assert_ne!(get_stamp(&file_name).unwrap(), stamp);
+
+        fs::remove_file(&file_name).unwrap();
+        assert!(is_unchanged(&file_name, &None));
+        assert_eq!(get_stamp(&file_name).unwrap(), None);
+    }
+}
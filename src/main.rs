@@ -1,6 +1,13 @@
+#![allow(clippy::needless_return, clippy::write_literal, clippy::print_literal)]
+
 use std::io::{self};
 use std::error::Error;
 use std::fmt::Write;
+use std::path::Path;
+use std::fs;
+use std::process;
+use std::num::ParseIntError;
+use std::time::{Duration, Instant};
 
 use termion::event::Key;
 use tui::backend::TermionBackend;
@@ -13,30 +20,142 @@ use tui::Terminal;
 mod gui;
 mod inventory;
 mod error;
+mod backup;
+mod migrate;
+mod storage;
+mod lock;
+mod journal;
+mod history;
+mod audit;
+mod cli;
+mod output;
+mod script;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 use crate::gui::{Event};
 
 //to attach to the process use :
 //echo 0 > /proc/sys/kernel/yama/ptrace_scope
 
 
-fn not_main() -> Result<(), Box<dyn Error>> 
+/// Options given on the command line: invi [--new] [FILE] [--script SCRIPT] [COMMAND [ARGS]]
+struct Options
+{
+    file_name : Option<String>,
+    new_file  : bool,
+    help      : bool,
+    /// the subcommand and its arguments, empty for the TUI
+    command   : Vec<String>,
+    script    : Option<String>,
+    script_options : script::ScriptOptions
+}
+
+fn parse_options(args : &[String]) -> Result<Options, Box<dyn Error>>
 {
-    let greeting_string = &inventory::get_file_location(inventory::FILE_NAME);
-    let mut context = match gui::AppContext::new()
+    let mut options = Options{file_name : None, new_file : false, help : false, command : Vec::new(),
+                              script : None, script_options : script::ScriptOptions::default()};
+    let mut iter = args.iter().enumerate();
+
+    while let Some((index, arg)) = iter.next()
     {
-        Ok(context) => context,
-        Err(err) => 
+        match arg.as_ref()
         {
-            inventory::new_inventory("tmp.json".to_string()).unwrap();
-            let mut con = gui::AppContext::new().unwrap();
-            con.write_to_terminal(&format!("Error while creating context:\n    {}\n",&err));
-            con.write_to_terminal(&format!("A temporary database will be used:(tmp.json)\n"));
-            con
+            s if cli::is_command(s) => {options.command = args[index..].to_vec(); break;}
+            "--script" => match iter.next()
+            {
+                Some((_, script)) => options.script = Some(script.clone()),
+                None => return Err(Box::new(error::GenericError::new("--script needs the script file".to_string())))
+            },
+            "--keep-going" => options.script_options.keep_going = true,
+            "--dry-run"    => options.script_options.dry_run = true,
+            "--new"       => options.new_file = true,
+            "-h" | "--help" => options.help = true,
+            s if s.starts_with('-') => return Err(Box::new(error::GenericError::new(format!("Unknown option {}, see --help", s)))),
+            _ if options.file_name.is_some() => return Err(Box::new(error::GenericError::new("Only one inventory file can be opened".to_string()))),
+            _ => options.file_name = Some(arg.clone())
+        }
+    }
+
+    if options.new_file && options.file_name.is_none()
+        {return Err(Box::new(error::GenericError::new("--new needs the name of the file to create".to_string())));}
+
+    if options.script.is_some() && !options.command.is_empty()
+        {return Err(Box::new(error::GenericError::new("Either run a script or a command".to_string())));}
+
+    if options.script.is_none() && (options.script_options.keep_going || options.script_options.dry_run)
+        {return Err(Box::new(error::GenericError::new("--keep-going and --dry-run need --script".to_string())));}
+
+    return Ok(options);
+}
+
+fn print_usage()
+{
+    println!("Usage: invi [--new] [FILE] [--script SCRIPT [--keep-going] [--dry-run]] [COMMAND [ARGS]]");
+    println!("    {:20}{}","FILE","the inventory to open, the default is base.json in the data directory");
+    println!("    {:20}{}","","files ending in .sqlite, .sqlite3 or .db are SQLite databases (sqlite feature)");
+    println!("    {:20}{}","--new","creates FILE as a new blank inventory");
+    println!("    {:20}{}","--script SCRIPT","runs the : commands in SCRIPT line by line, # starts a comment");
+    println!("    {:20}{}","--keep-going","runs the rest of the script after a command failed, without it nothing is written then");
+    println!("    {:20}{}","--dry-run","only reports what the script would change");
+    println!("    {:20}{}","-h | --help","prints this message");
+    println!("Without a command the inventory is opened in the TUI.");
+    cli::print_commands();
+}
+
+/// Resolves the file to open, creating it for --new or when the default file does not exist yet
+fn get_inventory_file(options : &Options, messages : &mut String) -> Result<String, Box<dyn Error>>
+{
+    let file_name = match &options.file_name
+    {
+        Some(file_name) => file_name.clone(),
+        None => 
+        {
+            match inventory::migrate_legacy_file()
+            {
+                Ok(Some(old_file)) => writeln!(messages, "Copied the inventory from {} to {}, the old file is no longer used and can be removed",
+                                               old_file.display(), inventory::get_file_location(inventory::FILE_NAME))?,
+                Err(err) => writeln!(messages, "Copying the inventory from the old location failed: {}", err)?,
+                _ => {}
+            }
+            return inventory::get_default_file();
         }
     };
 
+    if options.new_file
+    {
+        if Path::new(&file_name).exists()
+            {return Err(Box::new(error::GenericError::new(format!("{} already exists, open it without --new", file_name))));}
 
-    context.write_to_terminal(&format!("Using default file: {}\n",greeting_string));
+        storage::create_inventory(&file_name)?;
+        writeln!(messages, "Created the new inventory {}", file_name)?;
+    }
+    else if !Path::new(&file_name).exists()
+    {
+        return Err(Box::new(error::GenericError::new(format!("{} does not exist, use --new to create it", file_name))));
+    }
+
+    return Ok(file_name);
+}
+
+fn not_main(options : Options) -> Result<(), Box<dyn Error>> 
+{
+    let mut messages = String::new();
+    let file_name = get_inventory_file(&options, &mut messages)?;
+
+    let mut context = match gui::AppContext::new(&file_name)
+    {
+        Ok(context) => context,
+        Err(err) => return Err(Box::new(error::GenericError::new(format!("Loading {} failed: {}", file_name, err))))
+    };
+
+    context.write_to_terminal(&messages);
+    context.write_to_terminal(&format!("Using file: {}\n",file_name));
+
+    let problems = context.inventory.check().len();
+    if problems != 0
+    {
+        context.write_to_terminal(&format!("The inventory has {} integrity problem(s), run :fsck for details\n", problems));
+    }
 
 
     /* init all the terminal specific resources (from tui-rf example) */
@@ -57,11 +176,14 @@ fn not_main() -> Result<(), Box<dyn Error>>
         write!(terminal.backend_mut(),"{}", Goto(2 + context.cursor_pos as u16, text_field_pos)).unwrap();
         io::stdout().flush().ok();
 
-         match events.next().unwrap()
-         {
+        match events.next().unwrap()
+        {
             Event::Input(input) => match input 
             {
-                Key::Char('\n') => 
+                Key::Char('u') if context.normal_mode => {dispatch_input(":u", &mut context);}
+                Key::Ctrl('r') if context.normal_mode => {dispatch_input(":redo", &mut context);}
+
+                Key::Char('\n') if !context.normal_mode => 
                 {
                     let input = gui::get_input_str_and_clear(&mut context);
 
@@ -70,7 +192,8 @@ fn not_main() -> Result<(), Box<dyn Error>>
 
                 other => gui::handle_input_key(other, &mut context)
             },
-            _ => {}
+
+            Event::Tick => {context.tick(); autosave(&mut context);}
         }
     }
 
@@ -103,7 +226,7 @@ fn get_arguments(in_str :&str) -> Vec<String>
                 if quote_start {tmp_string.push(ch)}
                 else
                 {
-                    if tmp_string.len() == 0 {continue;}
+                    if tmp_string.is_empty() {continue;}
                     args.push(tmp_string.clone());
                     tmp_string.clear();
                 }
@@ -112,17 +235,77 @@ fn get_arguments(in_str :&str) -> Vec<String>
         }
     }
 
-    if tmp_string.len() != 0 {args.push(tmp_string.clone());}
+    if !tmp_string.is_empty() {args.push(tmp_string.clone());}
 
     return args;
 }
 
 
+/// The commands which change the inventory, they are written to the recovery journal
+static JOURNALED_COMMANDS: [&str; 23] =
+[
+    ":aitem", ":atag", ":acomp", ":acont", ":edit", ":take", ":put", ":tag", ":untag", ":mitem", ":mitems", ":mcont",
+    ":ditem", ":dtag", ":dcomp", ":dcomp!", ":dcont", ":dcont!", ":fsck!", ":restore", ":restore!", ":u", ":redo"
+];
+
+/// Executes the input, the changes of every command are audited, recorded in the undo history and
+/// the command is appended to the recovery journal. A command which fails changes nothing.
 fn dispatch_input(input : &str, context : &mut gui::AppContext) -> bool
+{
+    let command = get_arguments(input).first().cloned().unwrap_or_default();
+
+    if !JOURNALED_COMMANDS.contains(&command.as_str()) {return execute_input(input, context);}
+
+    if context.recovery_pending
+    {
+        context.write_error("There is a recovery journal, replay it with :recover or discard it with :recover! first");
+        context.scroll_items.push(input.to_string());
+        return false;
+    }
+
+    let undoing = command == ":u" || command == ":redo";
+    let state   = context.history.get_state();
+    let errors  = context.errors;
+
+    context.inventory.begin();
+    let quit = execute_input(input, context);
+
+    if context.errors != errors
+    {
+        context.inventory.rollback();
+        context.gui_dirty = true;
+        return quit;
+    }
+
+    let reverts = context.inventory.commit(input);
+
+    if !reverts.is_empty() || context.history.get_state() != state
+    {
+        if !undoing {context.history.record(input, reverts);}
+
+        if !context.read_only && !context.dry_run
+        {
+            if let Err(e) = journal::append(&context.file_name, input)
+                {context.write_error(&format!("Error while writing the recovery journal: {}", e));}
+        }
+    }
+
+    context.invi_dirty = !context.history.is_saved();
+    return quit;
+}
+
+/// Reports the error of a command, invalid arguments are counted as usage errors as well
+fn report_error(context : &mut gui::AppContext, e : Box<dyn Error>)
+{
+    if e.is::<error::UsageError>() || e.is::<ParseIntError>() {context.usage_errors += 1;}
+    context.write_error(&format!("{}", e));
+}
+
+fn execute_input(input : &str, context : &mut gui::AppContext) -> bool
 {
     let mut args = get_arguments(input);
 
-    if args.len() == 0 {return false;}
+    if args.is_empty() {return false;}
 
     let first_arg = args.remove(0);
 
@@ -137,38 +320,124 @@ fn dispatch_input(input : &str, context : &mut gui::AppContext) -> bool
             }
             return true;
         }
-        ":q!"  => { return true; }
+        ":q!"  =>
+        {
+            if context.invi_dirty
+            {
+                if let Err(e) = clear_journal(context)
+                    {context.write_error(&format!("Error while removing the recovery journal: {}", e));}
+            }
+            return true;
+        }
         ":ct" | "cls" =>{context.clear_terminal();}
         ":0"  => {context.layout = gui::InviLayout::Terminal}
         ":1"  => {context.layout = gui::InviLayout::Overview}
+        ":2"  => {context.layout = gui::InviLayout::Search}
         ":wq" => 
         {
-            if let Err(e) = write_back_file(context)
-                { writeln!(context.get_terminal_ref(),"Error while saving file: {}",e).unwrap(); }
+            if let Err(e) = write_back_file(context, false)
+                { context.write_error(&format!("Error while saving file: {}", e)); }
             else 
                 { return true; }
         }
-        ":w" => 
+        ":w" | ":w!" if args.is_empty() => 
         {
-            if let Err(e) = write_back_file(context)
-            {writeln!(context.get_terminal_ref(),"Error while saving file: {}",e).unwrap();}
+            if let Err(e) = write_back_file(context, first_arg.ends_with('!'))
+            {context.write_error(&format!("Error while saving file: {}", e));}
         }
+        ":w" | ":w!" => if let Err(e) = save_as(context, &args, first_arg.ends_with('!'))
+            {context.write_error(&format!("Error while saving file: {}", e));},
+
+        ":e" | ":e!" => if let Err(e) = open_file(context, &args, first_arg.ends_with('!'))
+            {context.write_error(&format!("Error while opening file: {}", e));},
+
+        ":new" | ":new!" => if let Err(e) = new_file(context, &args, first_arg.ends_with('!'))
+            {context.write_error(&format!("Error while creating file: {}", e));},
+
+        ":recover" | ":recover!" => if let Err(e) = recover(context, first_arg.ends_with('!'))
+            {context.write_error(&format!("Error while recovering: {}", e));},
+
+        ":u" | ":redo" => if let Err(e) = undo(context, first_arg == ":redo")
+            {report_error(context, e);},
+
+        ":ls" => if let Err(e) = list_entities(context, &args)
+            {report_error(context, e);},
+
+        ":source" => if let Err(e) = source_script(context, &args)
+            {context.write_error(&format!("Error while running the script: {}", e));},
+
+        ":show" => if let Err(e) = show_entity(context, &args)
+            {report_error(context, e);},
+
+        ":log" => if let Err(e) = show_log(context, &args)
+            {report_error(context, e);},
+
+        ":autosave" => if let Err(e) = set_autosave(context, &args)
+            {report_error(context, e);},
+
+        ":backups" => if let Err(e) = list_backups(context)
+            {context.write_error(&format!("Error while listing the backups: {}", e));},
+
+        ":restore" | ":restore!" => if let Err(e) = restore_backup(context, &args, first_arg.ends_with('!'))
+            {context.write_error(&format!("Error while restoring the backup: {}", e));},
+
         ":help" | ":?" | "help" | "?" | "hlp" | ":hlp" => 
             {print_help_msg(context);}
 
         ":aitem" => if let Err(e) = add_item(context, &args)
-            {writeln!(context.get_terminal_ref(),"{}",e).unwrap();},
+            {report_error(context, e);},
 
         ":atag"  => if let Err(e) = add_tag (context, &args)
-            {writeln!(context.get_terminal_ref(),"{}",e).unwrap();},
+            {report_error(context, e);},
 
         ":acomp"  => if let Err(e) = add_compartment(context, &args)
-            {writeln!(context.get_terminal_ref(),"{}",e).unwrap();},
+            {report_error(context, e);},
         
         ":acont"  => if let Err(e) = add_container(context, &args)
-            {writeln!(context.get_terminal_ref(),"{}",e).unwrap();},
+            {report_error(context, e);},
+
+        ":fsck" | ":fsck!" => {check_inventory(context, first_arg.ends_with('!'));}
+
+        ":edit" => if let Err(e) = edit_entity(context, &args)
+            {report_error(context, e);},
+
+        ":take" => if let Err(e) = change_stock(context, &args, false)
+            {report_error(context, e);},
+
+        ":put" => if let Err(e) = change_stock(context, &args, true)
+            {report_error(context, e);},
+
+        ":restock" => if let Err(e) = restock(context, &args)
+            {report_error(context, e);},
+
+        ":tag" | ":untag" => if let Err(e) = tag_entity(context, &args, first_arg == ":tag")
+            {report_error(context, e);},
+
+        ":mitem" => if let Err(e) = move_item(context, &args)
+            {report_error(context, e);},
 
-        _ => {context.write_to_terminal(&format!("No use for \"{}\" and args: {:?}\n",first_arg, args));}
+        ":mitems" => if let Err(e) = move_all_items(context, &args)
+            {report_error(context, e);},
+
+        ":mcont" => if let Err(e) = move_container(context, &args)
+            {report_error(context, e);},
+
+        ":ditem" => if let Err(e) = remove_item(context, &args)
+            {report_error(context, e);},
+
+        ":dtag"  => if let Err(e) = remove_tag(context, &args)
+            {report_error(context, e);},
+
+        ":dcomp" | ":dcomp!" => if let Err(e) = remove_compartment(context, &args, first_arg.ends_with('!'))
+            {report_error(context, e);},
+
+        ":dcont" | ":dcont!" => if let Err(e) = remove_container(context, &args, first_arg.ends_with('!'))
+            {report_error(context, e);},
+
+        s if s.starts_with(":/") => if let Err(e) = search(context, &input.trim_start()[2..])
+            {report_error(context, e);},
+
+        _ => {context.write_error(&format!("No use for \"{}\" and args: {:?}",first_arg, args));}
     }
 
     context.scroll_items.push(input.to_string());
@@ -178,22 +447,239 @@ fn dispatch_input(input : &str, context : &mut gui::AppContext) -> bool
 
 //todo read the is in hex
 
-fn write_back_file(context : &mut gui::AppContext) -> Result<(), Box<dyn Error>>
+/// Writes the inventory back to its file, unless the file changed on disk since it was loaded
+/// or another session has it locked. With force a changed file is overwritten anyway.
+fn write_back_file(context : &mut gui::AppContext, force : bool) -> Result<(), Box<dyn Error>>
 {
-    inventory::save_inventory(&context.inventory)?;
+    if context.read_only
+    {
+        return Err(Box::new(error::GenericError::new(format!("{} is locked by another invi session, save the changes to another file with :w <file>", context.file_name))));
+    }
+
+    if !force && !lock::is_unchanged(&context.file_name, &context.file_stamp) && lock::get_stamp(&context.file_name)? != context.file_stamp
+    {
+        return Err(Box::new(error::GenericError::new(format!("{} was changed on disk since it was loaded, reload it with :e! or overwrite it with :w!", context.file_name))));
+    }
+
+    context.storage.save(&context.inventory)?;
     context.invi_dirty = false;
+    context.history.mark_saved();
+    context.last_save  = Instant::now();
+    context.file_stamp = lock::get_stamp(&context.file_name)?;
+    return clear_journal(context);
+}
+
+/// Removes the recovery journal of the open file once its changes were written or discarded,
+/// a journal which was not recovered yet or belongs to another session is kept
+fn clear_journal(context : &gui::AppContext) -> Result<(), Box<dyn Error>>
+{
+    if context.read_only || context.recovery_pending {return Ok(());}
+    return journal::clear(&context.file_name);
+}
+
+/// Writes the inventory if autosave is on and its interval passed, called on every tick
+fn autosave(context : &mut gui::AppContext)
+{
+    if !context.autosave_due() {return;}
+
+    context.last_save = Instant::now();
+
+    if let Err(e) = write_back_file(context, false)
+        {context.write_error(&format!("Autosave failed: {}", e));}
+    else
+        {context.write_to_terminal(&format!("Autosaved {}\n", context.file_name));}
+}
+
+/// :u reverts the last change of the inventory, :redo applies the last undone change again
+fn undo(context : &mut gui::AppContext, redo : bool) -> Result<(), Box<dyn Error>>
+{
+    let command = if redo {context.history.redo(&mut context.inventory)} else {context.history.undo(&mut context.inventory)};
+
+    match command
+    {
+        Some(command) => context.write_to_terminal(&format!("{}: {}\n", if redo {"Redone"} else {"Undone"}, command)),
+        None => return Err(Box::new(error::GenericError::new(format!("There is nothing to {}", if redo {"redo"} else {"undo"}))))
+    }
+
     return Ok(());
 }
 
+/// :autosave <seconds> sets the autosave interval, 0 turns it off and without seconds it is shown
+fn set_autosave(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    if args.len() > 1
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
 
-fn add_container(context : &mut gui::AppContext, args : &Vec<String>) -> Result<(), Box<dyn Error>>
+    if let Some(secs) = args.first()
+    {
+        let secs = secs.parse::<u64>()?;
+        context.autosave = if secs == 0 {None} else {Some(Duration::from_secs(secs))};
+    }
+
+    match context.autosave
+    {
+        Some(interval) => context.write_to_terminal(&format!("Autosave every {} second(s)\n", interval.as_secs())),
+        None => context.write_to_terminal("Autosave is off\n")
+    }
+
+    return Ok(());
+}
+
+/// :recover replays the recovery journal of the open file, :recover! discards it
+fn recover(context : &mut gui::AppContext, discard : bool) -> Result<(), Box<dyn Error>>
+{
+    if !context.recovery_pending
+        {return Err(Box::new(error::GenericError::new("There is no recovery journal to replay".to_string())));}
+
+    let commands = journal::read(&context.file_name)?;
+    context.recovery_pending = false;
+    journal::clear(&context.file_name)?;
+
+    if discard
+    {
+        context.write_to_terminal(&format!("Discarded the recovery journal with {} change(s)\n", commands.len()));
+        return Ok(());
+    }
+
+    // the replayed commands are journaled again, so a crash now does not lose them
+    for command in commands.iter()
+    {
+        context.write_to_terminal(&format!("> {}\n", command));
+        dispatch_input(command, context);
+    }
+
+    context.write_to_terminal(&format!("Replayed {} change(s) from the recovery journal, write them with :w\n", commands.len()));
+    return Ok(());
+}
+
+fn check_clean(context : &gui::AppContext, force : bool, cmd : &str) -> Result<(), Box<dyn Error>>
+{
+    if context.invi_dirty && !force
+    {
+        return Err(Box::new(error::GenericError::new(format!("There are unwritten changes in the inventory, write the changes(:w) or discard them({}!)", cmd))));
+    }
+    return Ok(());
+}
+
+/// :w <file> writes the inventory to another file and continues with that one
+fn save_as(context : &mut gui::AppContext, args : &[String], force : bool) -> Result<(), Box<dyn Error>>
+{
+    if args.len() != 1
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    let file_name = &args[0];
+
+    if *file_name == context.file_name {return write_back_file(context, force);}
+
+    let exists = Path::new(file_name).exists();
+    if exists && !force
+        {return Err(Box::new(error::GenericError::new(format!("{} already exists, overwrite it with :w! {}", file_name, file_name))));}
+
+    let lock = match lock::try_lock(file_name)?
+    {
+        Some(lock) => lock,
+        None => return Err(Box::new(error::GenericError::new(format!("{} is locked by another invi session", file_name))))
+    };
+
+    let mut storage = storage::create_storage(file_name)?;
+    if let Err(e) = storage.save(&context.inventory)
+    {
+        // a database created for the save is not left behind empty, e.g. if the inventory has duplicate ids
+        drop(storage);
+        if !exists {let _ = fs::remove_file(file_name);}
+        return Err(e);
+    }
+    clear_journal(context)?;
+    context.saved_as(file_name, storage, lock)?;
+    context.write_to_terminal(&format!("Saved as {}\n", file_name));
+
+    return Ok(());
+}
+
+/// :e <file> opens another inventory, :e! without a file reloads the current one
+fn open_file(context : &mut gui::AppContext, args : &[String], force : bool) -> Result<(), Box<dyn Error>>
+{
+    if args.len() > 1 || (args.is_empty() && !force)
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    check_clean(context, force, ":e")?;
+    if context.invi_dirty {clear_journal(context)?;}
+
+    let file_name = match args.first() {Some(file_name) => file_name.clone(), None => context.file_name.clone()};
+    context.open_file(&file_name)?;
+    context.write_to_terminal(&format!("Opened {}\n", file_name));
+
+    return Ok(());
+}
+
+/// :new <file> creates a blank inventory and switches to it
+fn new_file(context : &mut gui::AppContext, args : &[String], force : bool) -> Result<(), Box<dyn Error>>
+{
+    if args.len() != 1
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    check_clean(context, force, ":new")?;
+
+    let file_name = &args[0];
+
+    if Path::new(file_name).exists()
+        {return Err(Box::new(error::GenericError::new(format!("{} already exists, open it with :e {}", file_name, file_name))));}
+
+    storage::create_inventory(file_name)?;
+    if context.invi_dirty {clear_journal(context)?;}
+    context.open_file(file_name)?;
+    context.write_to_terminal(&format!("Created {}\n", file_name));
+
+    return Ok(());
+}
+
+fn list_backups(context : &mut gui::AppContext) -> Result<(), Box<dyn Error>>
+{
+    let backups = backup::list_backups(&context.file_name)?;
+    let mut out = String::new();
+
+    if backups.is_empty() {writeln!(out, "There are no backups yet, they are created on every :w")?;}
+
+    for (index, bak) in backups.iter().enumerate()
+    {
+        writeln!(out, "    {:3} : {}  {}", index, bak.time.format("%Y-%m-%d %H:%M:%S"), bak.path.display())?;
+    }
+
+    context.write_to_terminal(&out);
+    return Ok(());
+}
+
+/// Loads backup <n> (0 is the newest) into the context, it still has to be written with :w
+fn restore_backup(context : &mut gui::AppContext, args : &[String], force : bool) -> Result<(), Box<dyn Error>>
+{
+    if args.len() != 1
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    check_clean(context, force, ":restore")?;
+
+    let index = args[0].parse::<usize>()?;
+    let backups = backup::list_backups(&context.file_name)?;
+
+    let bak = match backups.get(index)
+    {
+        Some(bak) => bak,
+        None => return Err(Box::new(error::GenericError::new(format!("There is no backup {}, see :backups", index))))
+    };
+
+    context.inventory.restore(storage::load_file(&bak.path.to_string_lossy())?);
+    context.invi_dirty = true;
+    context.write_to_terminal(&format!("Restored the backup from {}, write it with :w\n", bak.time.format("%Y-%m-%d %H:%M:%S")));
+
+    return Ok(());
+}
+
+fn add_container(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
 {
     if args.len() < 2
-        {return Err(Box::new(error::GenericError::new("Error invalid number of arguments".to_string())));}
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
 
     let name = &args[0];
-    let value = i64::from_str_radix(&args[1], 16)?;
-    let com_id : inventory::IdType = value as inventory::IdType;
+    let com_id = parse_id(&args[1])?;
     let tags = get_ids_from_args(&args[2..])?; 
 
     context.inventory.add_container(name,  com_id, tags)?;
@@ -202,10 +688,10 @@ fn add_container(context : &mut gui::AppContext, args : &Vec<String>) -> Result<
     return Ok(());
 }
 
-fn add_compartment(context : &mut gui::AppContext, args : &Vec<String>) -> Result<(), Box<dyn Error>>
+fn add_compartment(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
 {
     if args.len() != 1
-        {return Err(Box::new(error::GenericError::new("Error invalid number of arguments".to_string())));}
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
 
     let name = &args[0];
 
@@ -215,10 +701,10 @@ fn add_compartment(context : &mut gui::AppContext, args : &Vec<String>) -> Resul
     return Ok(());
 }
 
-fn add_tag(context : &mut gui::AppContext, args : &Vec<String>) -> Result<(), Box<dyn Error>>
+fn add_tag(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
 {
     if args.len() != 1
-        {return Err(Box::new(error::GenericError::new("Error invalid number of arguments".to_string())));}
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
 
     let name = &args[0];
 
@@ -228,28 +714,477 @@ fn add_tag(context : &mut gui::AppContext, args : &Vec<String>) -> Result<(), Bo
     return Ok(());
 }
 
+/// Ids are hex numbers, the 0x prefix is optional
+fn parse_id(id_str : &str) -> Result<inventory::IdType, Box<dyn Error>>
+{
+    let digits = id_str.trim_start_matches("0x").trim_start_matches("0X");
+    return Ok(inventory::IdType::from_str_radix(digits, 16)?);
+}
 
 fn get_ids_from_args(args : &[String]) -> Result<Vec<inventory::IdType>,Box<dyn Error>>
 {
     let mut tag_ids : Vec<inventory::IdType> = Vec::new();
 
-    for tag_str in args {  tag_ids.push(i64::from_str_radix(tag_str, 16)? as inventory::IdType); }
+    for tag_str in args {  tag_ids.push(parse_id(tag_str)?); }
 
     return Ok(tag_ids);
 }
 
-use std::i64;
-fn add_item(context : &mut gui::AppContext, args : &Vec<String>) -> Result<(), Box<dyn Error>>
+fn add_item(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    if args.len() < 2 || args.len() > 4
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    let con_id = parse_id(&args[1])?;
+    let name = &args[0];
+    let quantity = match args.get(2) {Some(qty) => qty.parse::<u32>()?, None => 1};
+    let unit = args.get(3).map_or("", |u| u.as_str());
+
+    context.inventory.add_item(name, con_id, quantity, unit)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn get_single_id(args : &[String]) -> Result<inventory::IdType, Box<dyn Error>>
+{
+    if args.len() != 1
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    return parse_id(&args[0]);
+}
+
+fn get_id_pair(args : &[String]) -> Result<(inventory::IdType, inventory::IdType), Box<dyn Error>>
 {
     if args.len() != 2
-        {return Err(Box::new(error::GenericError::new("Error invalid number of arguments".to_string())));}
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    let ids = get_ids_from_args(args)?;
+    return Ok((ids[0], ids[1]));
+}
 
-    let value = i64::from_str_radix(&args[1], 16)?;
+/// Number of entries :log without arguments shows
+const LOG_TAIL: usize = 20;
 
-    let con_id : inventory::IdType = value as inventory::IdType;
-    let name = &args[0];
+/// :log [kind] <id> shows the change log of the entities with the id, without arguments the last changes
+fn show_log(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let (kind, id) = match args.len()
+    {
+        0 => (None, None),
+        1 => (None, Some(parse_id(&args[0])?)),
+        2 => (Some(inventory::EntityKind::parse(&args[0])?), Some(parse_id(&args[1])?)),
+        _ => return Err(Box::new(error::UsageError::new("Error invalid number of arguments, use :log [kind] <id>".to_string())))
+    };
+
+    let log = &context.inventory.log;
+    let entries : Vec<&inventory::LogEntry> = match id
+    {
+        Some(id) => log.iter().filter(|e| e.id == id && kind.is_none_or(|k| e.kind == k)).collect(),
+        None => log.iter().skip(log.len().saturating_sub(LOG_TAIL)).collect()
+    };
+
+    let mut out = String::new();
+    let kinds = [inventory::EntityKind::Compartment, inventory::EntityKind::Container, inventory::EntityKind::Item, inventory::EntityKind::Tag];
 
-    context.inventory.add_item(name, con_id)?;
+    for k in kinds.iter().filter(|k| kind.is_none_or(|kind| kind == **k))
+    {
+        if let Some((name, audit)) = id.and_then(|id| context.inventory.get_audit(*k, id))
+        {
+            writeln!(out, "{} \"{}\": created {} by {}, last changed {} by {}", k, name,
+                     audit::local_time(&audit.created_at), audit.created_by, audit::local_time(&audit.modified_at), audit.modified_by)?;
+        }
+    }
+
+    if entries.is_empty() {writeln!(out, "No changes were logged")?;}
+
+    for e in entries.iter()
+    {
+        writeln!(out, "    {}  {:10} {:8} {:11} {:04X} {:20} {}", audit::local_time(&e.time), e.user, e.action, e.kind, e.id, e.name, e.command)?;
+    }
+
+    context.write_to_terminal(&out);
+    return Ok(());
+}
+
+fn check_inventory(context : &mut gui::AppContext, repair : bool)
+{
+    let problems = context.inventory.check();
+
+    if problems.is_empty()
+    {
+        context.write_to_terminal("fsck: the inventory is consistent\n");
+        return;
+    }
+
+    let term = context.get_terminal_ref();
+    writeln!(term, "fsck: {} problem(s) found:", problems.len()).unwrap();
+    for problem in problems.iter() {writeln!(term, "    {}", problem).unwrap();}
+
+    if !repair
+    {
+        context.write_error("run :fsck! to repair the inventory");
+        return;
+    }
+
+    context.inventory.repair();
+    context.invi_dirty = true;
+
+    let remaining = context.inventory.check();
+    let term = context.get_terminal_ref();
+    writeln!(term, "fsck: repaired, {} problem(s) remaining", remaining.len()).unwrap();
+    for problem in remaining.iter() {writeln!(term, "    {}", problem).unwrap();}
+    context.gui_dirty = true;
+}
+
+/// :edit <kind> <id> <field> <value>, the kinds are item, cont, comp and tag
+fn edit_entity(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    if args.len() != 4
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments, use :edit <kind> <id> <field> <value>".to_string())));}
+
+    let kind  = inventory::EntityKind::parse(&args[0])?;
+    let id    = parse_id(&args[1])?;
+    let field = args[2].as_str();
+    let value = &args[3];
+    let inv   = &mut context.inventory;
+
+    match (kind, field)
+    {
+        (inventory::EntityKind::Item,        "name") => inv.rename_item(id, value)?,
+        (inventory::EntityKind::Item,        "qty")  => inv.set_quantity(id, value.parse::<u32>()?)?,
+        (inventory::EntityKind::Item,        "min")  => inv.set_min_quantity(id, value.parse::<u32>()?)?,
+        (inventory::EntityKind::Item,        "unit") => inv.set_unit(id, value)?,
+        (inventory::EntityKind::Item,        "cont") => inv.move_item(id, parse_id(value)?)?,
+        (inventory::EntityKind::Container,   "name") => inv.rename_container(id, value)?,
+        (inventory::EntityKind::Container,   "comp") => inv.move_container(id, parse_id(value)?)?,
+        (inventory::EntityKind::Compartment, "name") => inv.rename_compartment(id, value)?,
+        (inventory::EntityKind::Tag,         "name") => inv.rename_tag(id, value)?,
+        _ => return Err(Box::new(error::GenericError::new(format!("Error the field \"{}\" of a {} can not be edited", field, kind))))
+    }
+
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+/// :take/:put <item_id> [amount], the amount defaults to 1
+fn change_stock(context : &mut gui::AppContext, args : &[String], put : bool) -> Result<(), Box<dyn Error>>
+{
+    if args.is_empty() || args.len() > 2
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    let id = parse_id(&args[0])?;
+    let amount = match args.get(1) {Some(amount) => amount.parse::<u32>()?, None => 1};
+
+    if put {context.inventory.put_item(id, amount)?;}
+    else   {context.inventory.take_item(id, amount)?;}
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+/// Lists all under-stocked items, with a file name the list is also written as shopping list
+fn restock(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    if args.len() > 1
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    let inv = &context.inventory;
+    let low = inv.restock_list();
+    let mut out = String::new();
+    let mut list = String::new();
+
+    if context.output != output::Format::Text
+    {
+        if !args.is_empty()
+            {return Err(Box::new(error::GenericError::new("The shopping list can only be written as text".to_string())));}
+
+        let result = inventory::SearchResult{compartments : Vec::new(), containers : Vec::new(), items : low, tags : Vec::new()};
+        output::write_result(&mut out, inv, &result, context.output)?;
+        context.write_to_terminal(&out);
+        return Ok(());
+    }
+
+    if low.is_empty() {writeln!(out, "Nothing to restock")?;}
+    else              {writeln!(out, "{} item(s) to restock:", low.len())?;}
+
+    for item in low.iter()
+    {
+        let shortfall = if item.unit.is_empty() {format!("{}", item.shortfall())} else {format!("{} {}", item.shortfall(), item.unit)};
+        writeln!(out, "    {:04X} : {} ({} of {} in stock, {} missing)", item.id, inv.item_path(item), item.quantity, item.min_quantity, shortfall)?;
+        writeln!(list, "[ ] {:>10}  {}  ({})", shortfall, item.name, inv.item_path(item))?;
+    }
+
+    if let Some(file_name) = args.first()
+    {
+        std::fs::write(file_name, list)?;
+        writeln!(out, "Shopping list written to {}", file_name)?;
+    }
+
+    context.write_to_terminal(&out);
+    return Ok(());
+}
+
+/// :tag/:untag <comp|cont|item> <id> <tag_id1> ... <tag_idn>
+fn tag_entity(context : &mut gui::AppContext, args : &[String], attach : bool) -> Result<(), Box<dyn Error>>
+{
+    if args.len() < 3
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments".to_string())));}
+
+    let kind = inventory::EntityKind::parse(&args[0])?;
+    let id = parse_id(&args[1])?;
+    let tag_ids = get_ids_from_args(&args[2..])?;
+
+    if attach {context.inventory.tag_entity(kind, id, &tag_ids)?;}
+    else      {context.inventory.untag_entity(kind, id, &tag_ids)?;}
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn move_item(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let (id, con_id) = get_id_pair(args)?;
+
+    context.inventory.move_item(id, con_id)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn move_all_items(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let (from_con_id, to_con_id) = get_id_pair(args)?;
+
+    context.inventory.move_all_items(from_con_id, to_con_id)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn move_container(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let (id, com_id) = get_id_pair(args)?;
+
+    context.inventory.move_container(id, com_id)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn remove_item(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let id = get_single_id(args)?;
+
+    context.inventory.remove_item(id)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn remove_container(context : &mut gui::AppContext, args : &[String], cascade : bool) -> Result<(), Box<dyn Error>>
+{
+    let id = get_single_id(args)?;
+
+    context.inventory.remove_container(id, cascade)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn remove_compartment(context : &mut gui::AppContext, args : &[String], cascade : bool) -> Result<(), Box<dyn Error>>
+{
+    let id = get_single_id(args)?;
+
+    context.inventory.remove_compartment(id, cascade)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn remove_tag(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let id = get_single_id(args)?;
+
+    context.inventory.remove_tag(id)?;
+    context.invi_dirty = true;
+
+    return Ok(());
+}
+
+fn search(context : &mut gui::AppContext, key_word : &str) -> Result<(), Box<dyn Error>>
+{
+    if key_word.trim().is_empty()
+        {return Err(Box::new(error::UsageError::new("Error nothing to search for, use :/<str>".to_string())));}
+
+    context.search_key = key_word.trim().to_string();
+    context.gui_dirty = true;
+
+    let result = inventory::search(key_word, &context.inventory);
+    let mut out = String::new();
+
+    if context.output != output::Format::Text
+    {
+        output::write_result(&mut out, &context.inventory, &result, context.output)?;
+        context.write_to_terminal(&out);
+        return Ok(());
+    }
+
+    if result.is_empty()
+    {
+        writeln!(out, "No match for \"{}\"", key_word.trim())?;
+        context.write_to_terminal(&out);
+        return Ok(());
+    }
+
+    writeln!(out, "Search results for \"{}\":", key_word.trim())?;
+    write_result(&mut out, &result)?;
+
+    context.write_to_terminal(&out);
+    return Ok(());
+}
+
+/// :ls [kind] lists every entity, or only the ones of one kind
+fn list_entities(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    if args.len() > 1
+        {return Err(Box::new(error::UsageError::new("Error invalid number of arguments, use :ls [kind]".to_string())));}
+
+    let mut result = inventory::list_all(&context.inventory);
+    if let Some(kind) = args.first() {result = result.only(inventory::EntityKind::parse(kind)?);}
+
+    let mut out = String::new();
+
+    if context.output != output::Format::Text {output::write_result(&mut out, &context.inventory, &result, context.output)?;}
+    else
+    {
+        if result.is_empty() {writeln!(out, "The inventory is empty")?;}
+        write_result(&mut out, &result)?;
+    }
+
+    context.write_to_terminal(&out);
+    return Ok(());
+}
+
+/// :source <file> [--keep-going] [--dry-run] runs the commands in the file
+fn source_script(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let mut options = script::ScriptOptions::default();
+    let mut file_name = None;
+
+    for arg in args
+    {
+        match arg.as_str()
+        {
+            "--keep-going" => options.keep_going = true,
+            "--dry-run"    => options.dry_run = true,
+            _ if file_name.is_none() => file_name = Some(arg.clone()),
+            _ => return Err(Box::new(error::UsageError::new("Error invalid number of arguments, use :source <file> [--keep-going] [--dry-run]".to_string())))
+        }
+    }
+
+    let file_name = match file_name
+    {
+        Some(file_name) => file_name,
+        None => return Err(Box::new(error::GenericError::new("Error the script file is missing".to_string())))
+    };
+
+    let summary = script::source(context, &file_name, options)?;
+    context.write_to_terminal(&format!("{}: {}\n", file_name, summary));
+
+    return Ok(());
+}
+
+/// :show [kind] <id> shows everything about the entities with the id
+fn show_entity(context : &mut gui::AppContext, args : &[String]) -> Result<(), Box<dyn Error>>
+{
+    let (kind, id) = match args.len()
+    {
+        1 => (None, parse_id(&args[0])?),
+        2 => (Some(inventory::EntityKind::parse(&args[0])?), parse_id(&args[1])?),
+        _ => return Err(Box::new(error::UsageError::new("Error invalid number of arguments, use :show [kind] <id>".to_string())))
+    };
+
+    let inv = &context.inventory;
+    let result = inventory::select_id(inv, kind, id);
+
+    if result.is_empty()
+        {return Err(Box::new(error::GenericError::new(format!("There is nothing with the id: {:04X}", id))));}
+
+    let mut out = String::new();
+
+    if context.output != output::Format::Text
+    {
+        output::write_result(&mut out, inv, &result, context.output)?;
+        context.write_to_terminal(&out);
+        return Ok(());
+    }
+
+    let tag_names = |tags : &[inventory::IdType]| -> String
+    {
+        return tags.iter().map(|id| inv.tags.iter().find(|t| t.id == *id).map_or(format!("{:04X}", id), |t| t.name.clone()))
+            .collect::<Vec<String>>().join(", ");
+    };
+
+    let write_audit = |out : &mut String, audit : &inventory::Audit| -> std::fmt::Result
+    {
+        writeln!(out, "    {:10}{} by {}", "created", audit::local_time(&audit.created_at), audit.created_by)?;
+        return writeln!(out, "    {:10}{} by {}", "changed", audit::local_time(&audit.modified_at), audit.modified_by);
+    };
+
+    for comp in result.compartments.iter()
+    {
+        writeln!(out, "compartment {:04X} \"{}\"", comp.id, comp.name)?;
+        writeln!(out, "    {:10}{}", "tags", tag_names(comp.get_tags()))?;
+        write_audit(&mut out, &comp.audit)?;
+    }
+
+    for cont in result.containers.iter()
+    {
+        writeln!(out, "container {:04X} \"{}\"", cont.id, cont.name)?;
+        writeln!(out, "    {:10}{}", "location", inv.container_path(cont))?;
+        writeln!(out, "    {:10}{}", "tags", tag_names(cont.get_tags()))?;
+        write_audit(&mut out, &cont.audit)?;
+    }
+
+    for item in result.items.iter()
+    {
+        writeln!(out, "item {:04X} \"{}\"", item.id, item.name)?;
+        writeln!(out, "    {:10}{}", "location", inv.item_path(item))?;
+        writeln!(out, "    {:10}{} (min {})", "quantity", item.quantity_str(), item.min_quantity)?;
+        writeln!(out, "    {:10}{}", "tags", tag_names(item.get_tags()))?;
+        write_audit(&mut out, &item.audit)?;
+    }
+
+    for tag in result.tags.iter()
+    {
+        writeln!(out, "tag {:04X} \"{}\"", tag.id, tag.name)?;
+        write_audit(&mut out, &tag.audit)?;
+    }
+
+    context.write_to_terminal(&out);
+    return Ok(());
+}
+
+/// Lists the entities grouped by their kind, containers and items with the id of their parent first
+fn write_result(out : &mut String, result : &inventory::SearchResult) -> std::fmt::Result
+{
+    if !result.compartments.is_empty() {writeln!(out, "  Compartments:")?;}
+    for comp in result.compartments.iter()
+        {writeln!(out, "    {:04X} : {}", comp.id, comp.name)?;}
+
+    if !result.containers.is_empty() {writeln!(out, "  Containers:")?;}
+    for cont in result.containers.iter()
+        {writeln!(out, "    {:04X} {:04X} : {}", cont.id_comp, cont.id, cont.name)?;}
+
+    if !result.items.is_empty() {writeln!(out, "  Items:")?;}
+    for item in result.items.iter()
+        {writeln!(out, "    {:04X} {:04X} : {}", item.id_cont, item.id, item.name)?;}
+
+    if !result.tags.is_empty() {writeln!(out, "  Tags:")?;}
+    for tag in result.tags.iter()
+        {writeln!(out, "    {:04X} : {}", tag.id, tag.name)?;}
 
     return Ok(());
 }
@@ -264,12 +1199,40 @@ fn print_help_msg(context : &mut gui::AppContext)
     writeln!(term,"Commands:").unwrap();
     writeln!(term,"    {:20}{}",":q","quit invi (save first)").unwrap();
     writeln!(term,"    {:20}{}",":ct","clear the terminals").unwrap();
+    writeln!(term,"    {:20}{}",":0 | :1 | :2","switch to the terminal, overview or search layout").unwrap();
     writeln!(term,"    {:20}{}","hlp | ? | help","prints this message").unwrap();
     writeln!(term,"    {:20}{}",":atag","adds a new tag <name>").unwrap();
     writeln!(term,"    {:20}{}",":acomp","adds a new compartment <name>").unwrap();
     writeln!(term,"    {:20}{}",":acont","adds a new container <name> <compartment_id> <tag_id1> <tag_id2> ... <tag_idn>").unwrap();
-    writeln!(term,"    {:20}{}",":aitem","adds a new item <name> <container_id>").unwrap();
-    writeln!(term,"    {:20}{}",":/<str>","used to search, items, containers, and compartments are listed also tag stuff").unwrap();
+    writeln!(term,"    {:20}{}",":aitem","adds a new item <name> <container_id> [quantity] [unit]").unwrap();
+    writeln!(term,"    {:20}{}",":edit","edits an entity <item|cont|comp|tag> <id> <field> <value>").unwrap();
+    writeln!(term,"    {:20}{}","","fields: name for all, cont/qty/unit/min for items, comp for containers").unwrap();
+    writeln!(term,"    {:20}{}",":take | :put","removes or adds stock of an item <item_id> [amount]").unwrap();
+    writeln!(term,"    {:20}{}",":restock","lists all items below their min stock level, [file] writes a shopping list").unwrap();
+    writeln!(term,"    {:20}{}",":tag | :untag","attaches or removes tags <comp|cont|item> <id> <tag_id1> ... <tag_idn>").unwrap();
+    writeln!(term,"    {:20}{}",":mitem","moves an item <item_id> <container_id>").unwrap();
+    writeln!(term,"    {:20}{}",":mitems","moves all items of a container <from_container_id> <to_container_id>").unwrap();
+    writeln!(term,"    {:20}{}",":mcont","moves a container <container_id> <compartment_id>").unwrap();
+    writeln!(term,"    {:20}{}",":ditem","deletes the item <item_id>").unwrap();
+    writeln!(term,"    {:20}{}",":dcont[!]","deletes the empty container <container_id>, with ! its items are deleted too").unwrap();
+    writeln!(term,"    {:20}{}",":dcomp[!]","deletes the empty compartment <compartment_id>, with ! its containers are deleted too").unwrap();
+    writeln!(term,"    {:20}{}",":dtag","deletes the tag <tag_id> and removes it from everything tagged with it").unwrap();
+    writeln!(term,"    {:20}{}",":u | :redo","undoes or redoes the last change since the last write, in normal mode (Esc) u and Ctrl-R do the same").unwrap();
+    writeln!(term,"    {:20}{}",":log","shows the changes of the entities with the id [kind] <id>, without an id the last changes").unwrap();
+    writeln!(term,"    {:20}{}",":w [file]","writes the inventory, with a file name it is saved as that file").unwrap();
+    writeln!(term,"    {:20}{}",":w!","writes even if the file was changed on disk or the target file exists").unwrap();
+    writeln!(term,"    {:20}{}",":e[!] <file>","opens another inventory, :e! discards unwritten changes (:e! alone reloads)").unwrap();
+    writeln!(term,"    {:20}{}",":new[!] <file>","creates a new blank inventory and opens it").unwrap();
+    writeln!(term,"    {:20}{}",":backups","lists the backups made by :w (INVI_BACKUPS, default 10), of SQLite files every 5 min at most").unwrap();
+    writeln!(term,"    {:20}{}",":restore[!]","loads the backup <n> from :backups, write it with :w").unwrap();
+    writeln!(term,"    {:20}{}",":recover[!]","replays the recovery journal left by a session which was not closed, ! discards it").unwrap();
+    writeln!(term,"    {:20}{}",":autosave","writes the inventory every <seconds> if it changed, 0 turns it off (default INVI_AUTOSAVE)").unwrap();
+    writeln!(term,"    {:20}{}",":fsck[!]","checks the inventory for broken references, with ! they are repaired").unwrap();
+    writeln!(term,"    {:20}{}",":ls","lists everything or only the entities of one [kind]").unwrap();
+    writeln!(term,"    {:20}{}",":source","runs the commands in <file>, # starts a comment [--keep-going] [--dry-run]").unwrap();
+    writeln!(term,"    {:20}{}",":show","shows everything about the entities with the id [kind] <id>").unwrap();
+    writeln!(term,"    {:20}{}",":/<str>","used to search, items, containers, and compartments are listed also tag stuff (:2 shows the last result)").unwrap();
+    writeln!(term,"    {:20}{}",":/#<tag>","lists everything tagged with a matching tag").unwrap();
 }
 
 
@@ -296,10 +1259,189 @@ fn print_help_msg(context : &mut gui::AppContext)
 
 fn main()
 {
-    match not_main()
+    let args : Vec<String> = std::env::args().skip(1).collect();
+
+    let options = match parse_options(&args)
     {
-        Err(err) => println!("Error while creating context:\n    {}\n",&err),
-        _ =>()
+        Ok(options) => options,
+        Err(err) => {eprintln!("{}", err); process::exit(2);}
+    };
+
+    if options.help {print_usage(); return;}
+
+    if !options.command.is_empty() || options.script.is_some()
+    {
+        let mut messages = String::new();
+        let file_name = get_inventory_file(&options, &mut messages);
+        eprint!("{}", messages);
+
+        let file_name = match file_name
+        {
+            Ok(file_name) => file_name,
+            Err(err) => {eprintln!("Error: {}", err); process::exit(1);}
+        };
+
+        match &options.script
+        {
+            Some(script) => process::exit(cli::run_script(&file_name, script, options.script_options)),
+            None => process::exit(cli::run(&file_name, &options.command))
+        }
+    }
+
+    if let Err(err) = not_main(options)
+    {
+        eprintln!("Error: {}",&err);
+        process::exit(1);
     }
 }
 
+
+#[cfg(test)]
+mod tests
+{
+    use std::env;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    /// An inventory with a shelf holding a box with two items, in a directory of its own which
+    /// the caller removes. Backups are turned off.
+    pub fn temp_context(name : &str) -> (PathBuf, gui::AppContext)
+    {
+        env::set_var("INVI_BACKUPS", "0");
+        let dir = env::temp_dir().join(format!("invi-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let file_name = dir.join("inv.json").to_string_lossy().to_string();
+        inventory::new_inventory(file_name.clone()).unwrap();
+        let mut context = gui::AppContext::new(&file_name).unwrap();
+
+        for command in [":acomp Shelf", ":acont Box 0", ":aitem Screw 0", ":aitem Nut 0"].iter()
+        {
+            dispatch_input(command, &mut context);
+        }
+        assert_eq!(context.errors, 0);
+
+        return (dir, context);
+    }
+
+    /// Like temp_context, but the inventory is written and the session is closed again
+    pub fn temp_file(name : &str) -> (PathBuf, String)
+    {
+        let (dir, mut context) = temp_context(name);
+        dispatch_input(":w", &mut context);
+        assert_eq!(context.errors, 0);
+        return (dir, context.file_name.clone());
+    }
+
+    /// The inventory without the log and the audit stamps, undoing is stamped as a change itself
+    pub fn entities(inventory : &inventory::Inventory) -> Value
+    {
+        let mut doc = serde_json::to_value(inventory).unwrap();
+        doc.as_object_mut().unwrap().remove("log");
+
+        for list in ["compartments", "containers", "items", "tags"].iter()
+        {
+            for entity in doc[*list].as_array_mut().unwrap() {entity.as_object_mut().unwrap().remove("audit");}
+        }
+
+        return doc;
+    }
+
+    #[test]
+    fn undoes_and_redoes_a_cascading_removal()
+    {
+        let (dir, mut context) = temp_context("undo");
+        let before = entities(&context.inventory);
+
+        dispatch_input(":dcomp! 0", &mut context);
+        assert!(context.inventory.compartments.is_empty() && context.inventory.containers.is_empty() && context.inventory.items.is_empty());
+        let removed = entities(&context.inventory);
+
+        dispatch_input(":u", &mut context);
+        assert_eq!(entities(&context.inventory), before);
+        assert!(context.inventory.check().is_empty());
+
+        dispatch_input(":redo", &mut context);
+        assert_eq!(entities(&context.inventory), removed);
+
+        dispatch_input(":u", &mut context);
+        assert_eq!(entities(&context.inventory), before);
+        assert_eq!(context.errors, 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_command_changes_nothing()
+    {
+        let (dir, mut context) = temp_context("failed");
+        let before = entities(&context.inventory);
+        let logged = context.inventory.log.len();
+        let journaled = journal::read(&context.file_name).unwrap().len();
+
+        dispatch_input(":aitem Washer 9", &mut context);
+        dispatch_input(":mitems 0 9", &mut context);
+        assert_eq!(context.errors, 2);
+        assert_eq!(entities(&context.inventory), before);
+        assert_eq!(context.inventory.log.len(), logged);
+        assert_eq!(journal::read(&context.file_name).unwrap().len(), journaled);
+
+        // the failed commands are not in the history either, :u undoes the last one which succeeded
+        dispatch_input(":u", &mut context);
+        assert_eq!(context.inventory.items.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_a_newer_journal_is_offered_for_recovery()
+    {
+        let (dir, context) = temp_context("journal");
+        let file_name = context.file_name.clone();
+        drop(context);
+
+        // the commands of the setup were journaled after the file was written
+        let context = gui::AppContext::new(&file_name).unwrap();
+        assert!(context.recovery_pending);
+        drop(context);
+
+        let journal = journal::get_journal_file(&file_name);
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options().write(true).open(&journal).unwrap().set_modified(an_hour_ago).unwrap();
+
+        let mut context = gui::AppContext::new(&file_name).unwrap();
+        assert!(!context.recovery_pending);
+        assert!(!Path::new(&journal).exists() && Path::new(&format!("{}.old", journal)).exists());
+
+        dispatch_input(":acomp Drawer", &mut context);
+        assert_eq!(context.errors, 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_after_a_touch_but_not_after_a_change()
+    {
+        let (dir, mut context) = temp_context("stamp");
+        let file_name = context.file_name.clone();
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options().write(true).open(&file_name).unwrap().set_modified(later).unwrap();
+        dispatch_input(":w", &mut context);
+        assert_eq!(context.errors, 0);
+        assert!(!context.invi_dirty);
+
+        dispatch_input(":acomp Drawer", &mut context);
+        fs::write(&file_name, fs::read_to_string(&file_name).unwrap().replace("Shelf", "Rack")).unwrap();
+        dispatch_input(":w", &mut context);
+        assert_eq!(context.errors, 1);
+        assert!(context.invi_dirty);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
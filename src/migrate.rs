@@ -0,0 +1,110 @@
+use serde_json::{json, Map, Value};
+
+use crate::error::GenericError;
+use crate::inventory::Inventory;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// The migration at index n upgrades a document of format version n to version n + 1,
+/// documents written before the version field existed are version 0
+const MIGRATIONS: [Migration; 2] =
+[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+];
+
+/// The format version written by this invi
+pub const FORMAT_VERSION: u32 = MIGRATIONS.len() as u32;
+
+pub fn get_version(doc : &Value) -> u32
+{
+    return doc.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+}
+
+/// Upgrades the document to the current format version, returns the version it had before
+pub fn migrate(doc : &mut Value) -> Result<u32, GenericError>
+{
+    let version = get_version(doc);
+
+    if version > FORMAT_VERSION
+    {
+        return Err(GenericError::new(format!("The file was written by a newer invi (format version {}, this invi supports up to {}), please update invi to open it", version, FORMAT_VERSION)));
+    }
+
+    let obj = match doc.as_object_mut()
+    {
+        Some(obj) => obj,
+        None => return Err(GenericError::new("The file is not an invi inventory".to_string()))
+    };
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize)
+    {
+        if let Err(e) = migration(obj)
+        {
+            return Err(GenericError::new(format!("Upgrading the file from format version {} failed: {}", from, e)));
+        }
+        obj.insert("version".to_string(), Value::from(from as u32 + 1));
+    }
+
+    return Ok(version);
+}
+
+/// Fixes which need the whole inventory, they run after the upgraded document was deserialized.
+/// Version 0 files numbered the containers per compartment and never counted them, so the ids are
+/// made unique and the counters are raised the same way :fsck! does it.
+pub fn upgrade_inventory(inventory : &mut Inventory, from : u32)
+{
+    if from == 0 {inventory.repair();}
+}
+
+fn get_array<'a>(obj : &'a mut Map<String, Value>, key : &str) -> Result<&'a mut Vec<Value>, String>
+{
+    return match obj.get_mut(key).and_then(|v| v.as_array_mut())
+    {
+        Some(array) => Ok(array),
+        None => Err(format!("the list \"{}\" is missing", key))
+    };
+}
+
+fn set_default(entity : &mut Value, key : &str, value : Value)
+{
+    if let Some(entity) = entity.as_object_mut()
+    {
+        entity.entry(key).or_insert(value);
+    }
+}
+
+/// Version 1 added quantities and units to items as well as tags on items and compartments
+fn migrate_v0_to_v1(obj : &mut Map<String, Value>) -> Result<(), String>
+{
+    for item in get_array(obj, "items")?.iter_mut()
+    {
+        set_default(item, "quantity", Value::from(1));
+        set_default(item, "unit", Value::from(""));
+        set_default(item, "min_quantity", Value::from(0));
+        set_default(item, "tags", Value::Array(Vec::new()));
+    }
+
+    for comp in get_array(obj, "compartments")?.iter_mut()
+    {
+        set_default(comp, "tags", Value::Array(Vec::new()));
+    }
+
+    return Ok(());
+}
+
+/// Version 2 added the audit of every entity and the change log
+fn migrate_v1_to_v2(obj : &mut Map<String, Value>) -> Result<(), String>
+{
+    for list in ["compartments", "containers", "items", "tags"].iter()
+    {
+        for entity in get_array(obj, list)?.iter_mut()
+        {
+            set_default(entity, "audit", json!({"created_at" : "", "created_by" : "", "modified_at" : "", "modified_by" : ""}));
+        }
+    }
+
+    obj.entry("log").or_insert(Value::Array(Vec::new()));
+
+    return Ok(());
+}
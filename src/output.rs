@@ -0,0 +1,120 @@
+use std::error::Error;
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::inventory::{Audit, IdType, Inventory, SearchResult};
+
+/// Version of the JSON output, it changes only if fields are removed or change their meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How the queries (ls, find, show and restock) write their results
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format
+{
+    Text,
+    /// one document: {"schema": 1, "compartments": [..], "containers": [..], "items": [..], "tags": [..]}
+    Json,
+    /// one entity record per line, compartments first, then containers, items and tags
+    Ndjson
+}
+
+/// One entity of a result, "kind" tells which of the fields are there.
+/// Ids are numbers here, the TUI shows them as hex.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Record<'a>
+{
+    Compartment
+    {
+        id    : IdType,
+        name  : &'a str,
+        tags  : &'a [IdType],
+        audit : &'a Audit
+    },
+    Container
+    {
+        id          : IdType,
+        name        : &'a str,
+        compartment : IdType,
+        /// "compartment → container"
+        path        : String,
+        tags        : &'a [IdType],
+        audit       : &'a Audit
+    },
+    Item
+    {
+        id           : IdType,
+        name         : &'a str,
+        container    : IdType,
+        /// "compartment → container → item"
+        path         : String,
+        quantity     : u32,
+        unit         : &'a str,
+        min_quantity : u32,
+        /// how many are missing to reach min_quantity, 0 if the stock is fine
+        shortfall    : u32,
+        tags         : &'a [IdType],
+        audit        : &'a Audit
+    },
+    Tag
+    {
+        id    : IdType,
+        name  : &'a str,
+        audit : &'a Audit
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a>
+{
+    schema       : u32,
+    compartments : Vec<Record<'a>>,
+    containers   : Vec<Record<'a>>,
+    items        : Vec<Record<'a>>,
+    tags         : Vec<Record<'a>>
+}
+
+fn get_document<'a>(inv : &Inventory, result : &SearchResult<'a>) -> Document<'a>
+{
+    return Document
+    {
+        schema       : SCHEMA_VERSION,
+        compartments : result.compartments.iter().map(|c| Record::Compartment
+        {
+            id : c.id, name : &c.name, tags : c.get_tags(), audit : &c.audit
+        }).collect(),
+        containers   : result.containers.iter().map(|c| Record::Container
+        {
+            id : c.id, name : &c.name, compartment : c.id_comp, path : inv.container_path(c), tags : c.get_tags(), audit : &c.audit
+        }).collect(),
+        items        : result.items.iter().map(|i| Record::Item
+        {
+            id : i.id, name : &i.name, container : i.id_cont, path : inv.item_path(i), quantity : i.quantity, unit : &i.unit,
+            min_quantity : i.min_quantity, shortfall : i.shortfall(), tags : i.get_tags(), audit : &i.audit
+        }).collect(),
+        tags         : result.tags.iter().map(|t| Record::Tag
+        {
+            id : t.id, name : &t.name, audit : &t.audit
+        }).collect()
+    };
+}
+
+/// Writes the result as JSON or NDJSON, nothing is written for the text format
+pub fn write_result(out : &mut String, inv : &Inventory, result : &SearchResult, format : Format) -> Result<(), Box<dyn Error>>
+{
+    let doc = get_document(inv, result);
+
+    match format
+    {
+        Format::Text => {}
+        Format::Json => writeln!(out, "{}", serde_json::to_string_pretty(&doc)?)?,
+        Format::Ndjson =>
+        {
+            let records = doc.compartments.iter().chain(doc.containers.iter()).chain(doc.items.iter()).chain(doc.tags.iter());
+            for record in records {writeln!(out, "{}", serde_json::to_string(record)?)?;}
+        }
+    }
+
+    return Ok(());
+}
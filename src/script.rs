@@ -0,0 +1,225 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::mem;
+
+use crate::gui::AppContext;
+
+/// Commands which leave the open inventory or would run scripts recursively
+static FORBIDDEN: [&str; 8] = [":q", ":q!", ":wq", ":e", ":e!", ":new", ":new!", ":source"];
+
+/// Commands which write files, a dry run only reports them
+static WRITING: [&str; 2] = [":w", ":w!"];
+
+/// Commands which delete the recovery journal or change the session, a dry run can not simulate them
+static NOT_SIMULATED: [&str; 3] = [":recover", ":recover!", ":autosave"];
+
+#[derive(Clone, Copy, Default)]
+pub struct ScriptOptions
+{
+    /// run the remaining lines after a command failed instead of stopping
+    pub keep_going : bool,
+    /// run the script on a copy of the inventory and report what would change
+    pub dry_run    : bool
+}
+
+#[derive(Default)]
+pub struct Summary
+{
+    pub commands : usize,
+    pub failed   : usize,
+    pub created  : usize,
+    pub changed  : usize,
+    pub removed  : usize,
+    /// the line the script stopped at because of an error
+    pub stopped  : Option<usize>,
+    pub dry_run  : bool,
+    /// a :w of the script wrote the inventory
+    pub written  : bool
+}
+
+impl fmt::Display for Summary
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} command(s), {} failed, {} change(s){}: {} created, {} changed, {} removed",
+               self.commands, self.failed, self.created + self.changed + self.removed,
+               if self.dry_run {" would be made"} else {""}, self.created, self.changed, self.removed)?;
+
+        if let Some(line) = self.stopped {write!(f, ", stopped at line {}", line)?;}
+        return Ok(());
+    }
+}
+
+/// The commands of the script with their line numbers, empty lines and comments starting with # are skipped
+fn read_commands(file_name : &str) -> Result<Vec<(usize, String)>, Box<dyn Error>>
+{
+    let commands = fs::read_to_string(file_name)?.lines().enumerate()
+        .map(|(index, line)| (index + 1, line.trim().to_string()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    return Ok(commands);
+}
+
+/// Runs the command of one line, returns false if it failed
+fn run_line(context : &mut AppContext, line : &str, options : ScriptOptions, summary : &mut Summary) -> bool
+{
+    let command = line.split_whitespace().next().unwrap_or("");
+
+    if FORBIDDEN.contains(&command)
+    {
+        context.write_error(&format!("{} can not be used in scripts", command));
+        return false;
+    }
+
+    if options.dry_run && WRITING.contains(&command)
+    {
+        context.write_to_terminal("would write the inventory\n");
+        return true;
+    }
+
+    if options.dry_run && NOT_SIMULATED.contains(&command)
+    {
+        context.write_error(&format!("{} can not be used in a dry run", command));
+        return false;
+    }
+
+    // the shopping list of :restock <file> is only shown
+    let args = crate::get_arguments(line);
+    let list_file = if options.dry_run && command == ":restock" {args.get(1)} else {None};
+
+    let errors = context.errors;
+    let logged = context.inventory.log.len();
+
+    crate::dispatch_input(if list_file.is_some() {command} else {line}, context);
+
+    if let Some(file_name) = list_file
+    {
+        if context.errors == errors {context.write_to_terminal(&format!("would write the shopping list to {}\n", file_name));}
+    }
+
+    let changes : Vec<_> = context.inventory.log.iter().skip(logged).cloned().collect();
+    for change in changes.iter()
+    {
+        match change.action.as_str()
+        {
+            "created" => summary.created += 1,
+            "removed" => summary.removed += 1,
+            _         => summary.changed += 1
+        }
+
+        if options.dry_run
+        {
+            context.write_to_terminal(&format!("would {} {} {:04X} \"{}\"\n", change.action.trim_end_matches('d'), change.kind, change.id, change.name));
+        }
+    }
+
+    if context.errors != errors {return false;}
+
+    if WRITING.contains(&command) {summary.written = true;}
+    return true;
+}
+
+/// Runs every line of the script through dispatch_input as if it was typed in
+pub fn source(context : &mut AppContext, file_name : &str, options : ScriptOptions) -> Result<Summary, Box<dyn Error>>
+{
+    return source_with(context, file_name, options, &mut |_, _| {});
+}
+
+/// Like source, after every line on_line is called with whether the command succeeded,
+/// e.g. to write the output of the line somewhere else than the terminal
+pub fn source_with(context : &mut AppContext, file_name : &str, options : ScriptOptions,
+                   on_line : &mut dyn FnMut(&mut AppContext, bool)) -> Result<Summary, Box<dyn Error>>
+{
+    let commands = read_commands(file_name)?;
+    let mut summary = Summary{dry_run : options.dry_run, ..Summary::default()};
+
+    // a dry run works on a copy, the history and the journal stay as they are
+    let saved = if options.dry_run {Some((context.inventory.clone(), mem::take(&mut context.history), context.invi_dirty))} else {None};
+    context.dry_run = options.dry_run;
+
+    for (number, line) in commands.iter()
+    {
+        context.write_to_terminal(&format!("{}> {}\n", number, line));
+        summary.commands += 1;
+
+        let succeeded = run_line(context, line, options, &mut summary);
+        on_line(context, succeeded);
+        if succeeded {continue;}
+
+        summary.failed += 1;
+        if !options.keep_going
+        {
+            summary.stopped = Some(*number);
+            break;
+        }
+    }
+
+    context.dry_run = false;
+
+    if let Some((inventory, history, dirty)) = saved
+    {
+        context.inventory  = inventory;
+        context.history    = history;
+        context.invi_dirty = dirty;
+        context.gui_dirty  = true;
+    }
+
+    return Ok(summary);
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::journal;
+    use crate::tests::{entities, temp_context};
+
+    #[test]
+    fn a_dry_run_changes_nothing()
+    {
+        let (dir, mut context) = temp_context("dry-run");
+        let script = dir.join("script.txt").to_string_lossy().to_string();
+        fs::write(&script, "# restock\n:aitem Washer 0\n:take 1 1\n\n:w\n:dcomp! 0\n").unwrap();
+
+        let before = entities(&context.inventory);
+        let logged = context.inventory.log.len();
+        let state = context.history.get_state();
+        let journaled = journal::read(&context.file_name).unwrap();
+        let file = fs::read(&context.file_name).unwrap();
+
+        let summary = source(&mut context, &script, ScriptOptions{keep_going : false, dry_run : true}).unwrap();
+        assert_eq!((summary.commands, summary.failed, summary.stopped, summary.written), (4, 0, None, false));
+        assert_eq!((summary.created, summary.changed, summary.removed), (1, 2, 5));
+        assert!(context.get_terminal_ref().contains("would write the inventory"));
+
+        assert_eq!(entities(&context.inventory), before);
+        assert_eq!(context.inventory.log.len(), logged);
+        assert_eq!(context.history.get_state(), state);
+        assert_eq!(journal::read(&context.file_name).unwrap(), journaled);
+        assert_eq!(fs::read(&context.file_name).unwrap(), file);
+        assert!(context.invi_dirty && !context.dry_run);
+
+        // the history is the one from before the script
+        crate::dispatch_input(":u", &mut context);
+        assert_eq!(context.inventory.items.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stops_at_the_first_failed_command()
+    {
+        let (dir, mut context) = temp_context("script");
+        let script = dir.join("script.txt").to_string_lossy().to_string();
+        fs::write(&script, ":acomp Drawer\n:w\n:aitem Washer 9\n:acomp Cupboard\n").unwrap();
+
+        let summary = source(&mut context, &script, ScriptOptions::default()).unwrap();
+        assert_eq!((summary.commands, summary.failed, summary.stopped, summary.written), (3, 1, Some(3), true));
+        assert_eq!(context.inventory.compartments.len(), 2);
+        assert!(!context.invi_dirty);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,346 @@
+use std::error::Error;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde_json::Value;
+
+use crate::inventory::{self, EntityKind, IdType, Inventory};
+use crate::storage::{self, Storage};
+use crate::error::GenericError;
+
+/// A single changed entity
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Change
+{
+    Upsert(EntityKind, IdType),
+    Remove(EntityKind, IdType)
+}
+
+/// The tables are created by the first save, opening a database never changes it
+static SCHEMA: &str =
+    "CREATE TABLE IF NOT EXISTS header   (id INTEGER PRIMARY KEY CHECK (id = 0), data TEXT NOT NULL);
+     CREATE TABLE IF NOT EXISTS entities (kind TEXT NOT NULL, id INTEGER NOT NULL, data TEXT NOT NULL, PRIMARY KEY (kind, id));
+     CREATE TABLE IF NOT EXISTS log      (seq INTEGER PRIMARY KEY, data TEXT NOT NULL);";
+
+/// A save only writes the changed rows, but a backup copies the whole database,
+/// so it is backed up on the first save and then at most once in this interval
+const BACKUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+static ENTITY_LISTS: [(&str, EntityKind); 4] =
+[
+    ("compartments", EntityKind::Compartment),
+    ("containers",   EntityKind::Container),
+    ("items",        EntityKind::Item),
+    ("tags",         EntityKind::Tag)
+];
+
+/// The serialized inventory split into its entities and the rest of the document (version, counters),
+/// the change log is not part of it
+struct Document
+{
+    header   : Value,
+    entities : HashMap<(EntityKind, IdType), Value>,
+    /// the order of the entities in the inventory
+    order    : Vec<(EntityKind, IdType)>
+}
+
+fn get_list_name(kind : EntityKind) -> &'static str
+{
+    return ENTITY_LISTS.iter().find(|(_, k)| *k == kind).map(|(name, _)| *name).unwrap_or("");
+}
+
+fn split_document(inventory : &Inventory) -> Result<Document, Box<dyn Error>>
+{
+    let mut header = serde_json::to_value(inventory)?;
+    if let Some(header) = header.as_object_mut() {header.remove("log");}
+
+    let mut entities = HashMap::new();
+    let mut order = Vec::new();
+
+    for (list, kind) in ENTITY_LISTS.iter()
+    {
+        let values = match header.get_mut(*list).map(Value::take)
+        {
+            Some(Value::Array(values)) => values,
+            _ => return Err(Box::new(GenericError::new(format!("The inventory has no list {}", list))))
+        };
+
+        for value in values
+        {
+            let id = value.get("id").and_then(|id| id.as_u64()).unwrap_or(0) as IdType;
+
+            // the rows are keyed by the id, a duplicate would overwrite the other entity
+            if entities.insert((*kind, id), value).is_some()
+            {
+                return Err(Box::new(GenericError::new(format!("The inventory has more than one {} with the id {:04X}, repair it with :fsck! before saving", kind, id))));
+            }
+            order.push((*kind, id));
+        }
+
+        header[*list] = Value::Array(Vec::new());
+    }
+
+    return Ok(Document{header, entities, order});
+}
+
+/// Joins the header and the entities back into one document, the entities are in the given order
+fn join_document(mut header : Value, entities : Vec<(EntityKind, Value)>) -> Value
+{
+    for (kind, value) in entities
+    {
+        if let Some(Value::Array(values)) = header.get_mut(get_list_name(kind))
+        {
+            values.push(value);
+        }
+    }
+
+    return header;
+}
+
+/// The changes needed to get from the old entities to the new document, in the order of the document
+fn diff(old : &HashMap<(EntityKind, IdType), Value>, new : &Document) -> Vec<Change>
+{
+    let mut changes = Vec::new();
+
+    for key in old.keys()
+    {
+        if !new.entities.contains_key(key) {changes.push(Change::Remove(key.0, key.1));}
+    }
+
+    for key in new.order.iter()
+    {
+        if old.get(key) != new.entities.get(key) {changes.push(Change::Upsert(key.0, key.1));}
+    }
+
+    return changes;
+}
+
+/// Stores every entity in its own row, so a save only writes the entities which changed.
+/// The change log has a table of its own, a save only appends the new entries.
+pub struct SqliteStorage
+{
+    file_name : String,
+    conn      : Connection,
+    /// the entities as they are stored in the database, None until the database was read or written
+    written   : Option<HashMap<(EntityKind, IdType), Value>>,
+    /// the number of log entries in the database, None if the log has to be written as a whole
+    logged    : Option<usize>,
+    /// None until the first backup of this session
+    backed_up : Option<Instant>
+}
+
+impl SqliteStorage
+{
+    /// Opens an existing database, a missing file is an error
+    pub fn open(file_name : &str) -> Result<SqliteStorage, Box<dyn Error>>
+    {
+        if !Path::new(file_name).exists()
+            {return Err(Box::new(GenericError::new(format!("The database {} does not exist", file_name))));}
+
+        return SqliteStorage::connect(file_name, OpenFlags::SQLITE_OPEN_READ_WRITE);
+    }
+
+    /// Creates the database file if it does not exist
+    pub fn create(file_name : &str) -> Result<SqliteStorage, Box<dyn Error>>
+    {
+        return SqliteStorage::connect(file_name, OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE);
+    }
+
+    fn connect(file_name : &str, flags : OpenFlags) -> Result<SqliteStorage, Box<dyn Error>>
+    {
+        let conn = Connection::open_with_flags(file_name, flags)?;
+        return Ok(SqliteStorage{file_name : file_name.to_string(), conn, written : None, logged : None, backed_up : None});
+    }
+
+    fn has_table(&self, name : &str) -> Result<bool, Box<dyn Error>>
+    {
+        let count : i64 = self.conn.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1", params![name], |row| row.get(0))?;
+        return Ok(count != 0);
+    }
+
+    fn write(&mut self, inventory : &Inventory, changes : &[Change], all : bool) -> Result<(), Box<dyn Error>>
+    {
+        let doc = split_document(inventory)?;
+        let tx = self.conn.transaction()?;
+        tx.execute_batch(SCHEMA)?;
+
+        tx.execute("INSERT INTO header (id, data) VALUES (0, ?1) ON CONFLICT(id) DO UPDATE SET data = excluded.data",
+                   params![doc.header.to_string()])?;
+
+        if all {tx.execute("DELETE FROM entities", [])?;}
+
+        for change in changes
+        {
+            match change
+            {
+                Change::Upsert(kind, id) =>
+                {
+                    if let Some(value) = doc.entities.get(&(*kind, *id))
+                    {
+                        tx.execute("INSERT INTO entities (kind, id, data) VALUES (?1, ?2, ?3) ON CONFLICT(kind, id) DO UPDATE SET data = excluded.data",
+                                   params![kind.to_string(), id, value.to_string()])?;
+                    }
+                }
+                Change::Remove(kind, id) =>
+                {
+                    tx.execute("DELETE FROM entities WHERE kind = ?1 AND id = ?2", params![kind.to_string(), id])?;
+                }
+            }
+        }
+
+        // the log only grows, unless another inventory was saved in between
+        let logged = match self.logged
+        {
+            Some(logged) if logged <= inventory.log.len() => logged,
+            _ => {tx.execute("DELETE FROM log", [])?; 0}
+        };
+
+        for entry in inventory.log[logged..].iter()
+        {
+            tx.execute("INSERT INTO log (data) VALUES (?1)", params![serde_json::to_string(entry)?])?;
+        }
+
+        tx.commit()?;
+        self.written = Some(doc.entities);
+        self.logged  = Some(inventory.log.len());
+
+        if self.backed_up.is_some_and(|time| time.elapsed() < BACKUP_INTERVAL) {return Ok(());}
+        self.backed_up = Some(Instant::now());
+        return storage::backup_after_save(&self.file_name);
+    }
+}
+
+impl Storage for SqliteStorage
+{
+    fn load(&mut self) -> Result<Inventory, Box<dyn Error>>
+    {
+        let header : Option<String> = self.conn.query_row("SELECT data FROM header WHERE id = 0", [], |row| row.get(0)).optional()?;
+
+        let header = match header
+        {
+            Some(header) => serde_json::from_str(&header)?,
+            None => serde_json::to_value(Inventory::new())?
+        };
+
+        let mut stmt = self.conn.prepare("SELECT kind, data FROM entities ORDER BY rowid")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+        let mut entities = Vec::new();
+        for row in rows
+        {
+            let (kind, data) = row?;
+            entities.push((EntityKind::parse(&kind)?, serde_json::from_str(&data)?));
+        }
+
+        // databases written before the log had its own table do not have it yet
+        let mut log = Vec::new();
+        if self.has_table("log")?
+        {
+            let mut stmt = self.conn.prepare("SELECT data FROM log ORDER BY seq")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            for row in rows {log.push(serde_json::from_str(&row?)?);}
+        }
+
+        // older databases kept the log in the header, it is moved to its table with the next save
+        let in_header = header.get("log").and_then(Value::as_array).is_some_and(|log| !log.is_empty());
+        let mut inventory = inventory::from_document(join_document(header, entities))?;
+        inventory.log.extend(log);
+
+        self.written = Some(split_document(&inventory)?.entities);
+        self.logged  = if in_header {None} else {Some(inventory.log.len())};
+
+        return Ok(inventory);
+    }
+
+    fn save(&mut self, inventory : &Inventory) -> Result<(), Box<dyn Error>>
+    {
+        let doc = split_document(inventory)?;
+
+        return match &self.written
+        {
+            Some(written) => {let changes = diff(written, &doc); self.write(inventory, &changes, false)}
+            None =>
+            {
+                let changes : Vec<Change> = doc.order.iter().map(|(kind, id)| Change::Upsert(*kind, *id)).collect();
+                self.write(inventory, &changes, true)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+    use crate::lock;
+
+    fn add_screws(inventory : &mut Inventory)
+    {
+        inventory.begin();
+        inventory.add_compartment("Shelf");
+        inventory.add_container("Box", 0, Vec::new()).unwrap();
+        inventory.add_item("M3 screw", 0, 100, "pcs").unwrap();
+        inventory.commit(":aitem \"M3 screw\" 0 100 pcs");
+    }
+
+    /// A database file in the temp directory which does not exist yet, backups are turned off
+    fn temp_file(name : &str) -> String
+    {
+        env::set_var("INVI_BACKUPS", "0");
+        let file_name = env::temp_dir().join(format!("invi-test-{}-{}.db", name, process::id())).to_string_lossy().to_string();
+        let _ = fs::remove_file(&file_name);
+        return file_name;
+    }
+
+    #[test]
+    fn saves_and_loads_the_inventory()
+    {
+        let file_name = temp_file("load");
+
+        assert!(SqliteStorage::open(&file_name).is_err());
+
+        let mut inventory = Inventory::new();
+        add_screws(&mut inventory);
+
+        let mut storage = SqliteStorage::create(&file_name).unwrap();
+        storage.save(&inventory).unwrap();
+        let stamp = lock::get_stamp(&file_name).unwrap();
+
+        // the second save only writes the changed item and appends to the log
+        inventory.begin();
+        inventory.take_item(0, 10).unwrap();
+        inventory.commit(":take 0 10");
+        storage.save(&inventory).unwrap();
+        assert_ne!(lock::get_stamp(&file_name).unwrap(), stamp);
+
+        let loaded = SqliteStorage::open(&file_name).unwrap().load().unwrap();
+        fs::remove_file(&file_name).unwrap();
+
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&inventory).unwrap());
+        assert_eq!(loaded.items[0].quantity, 90);
+        assert_eq!(loaded.log.len(), 4);
+        assert_eq!(loaded.log[3].command, ":take 0 10");
+    }
+
+    #[test]
+    fn refuses_to_save_duplicate_ids()
+    {
+        let file_name = temp_file("duplicate");
+
+        let mut inventory = Inventory::new();
+        add_screws(&mut inventory);
+        inventory.add_item("M3 nut", 0, 100, "pcs").unwrap();
+        inventory.items[1].id = 0;
+
+        let result = SqliteStorage::create(&file_name).unwrap().save(&inventory);
+        fs::remove_file(&file_name).unwrap();
+
+        assert!(result.unwrap_err().to_string().contains(":fsck!"));
+    }
+}
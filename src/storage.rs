@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::inventory::{self, Inventory};
+use crate::error::GenericError;
+use crate::backup;
+
+/// Abstracts where and how an inventory is stored
+pub trait Storage
+{
+    fn load(&mut self) -> Result<Inventory, Box<dyn Error>>;
+
+    /// Writes the inventory, backends which keep track of what they stored only write the changes
+    fn save(&mut self, inventory : &Inventory) -> Result<(), Box<dyn Error>>;
+}
+
+/// The default backend, the inventory is one JSON document which is rewritten on every save
+pub struct JsonStorage
+{
+    file_name : String
+}
+
+impl JsonStorage
+{
+    pub fn new(file_name : &str) -> JsonStorage
+    {
+        return JsonStorage{file_name : file_name.to_string()};
+    }
+}
+
+impl Storage for JsonStorage
+{
+    fn load(&mut self) -> Result<Inventory, Box<dyn Error>>
+    {
+        return inventory::load_inventory(self.file_name.clone());
+    }
+
+    fn save(&mut self, inventory : &Inventory) -> Result<(), Box<dyn Error>>
+    {
+        return inventory::save_inventory(inventory, &self.file_name);
+    }
+}
+
+fn is_sqlite_file(file_name : &str) -> bool
+{
+    return match Path::new(file_name).extension().and_then(|e| e.to_str())
+    {
+        Some(ext) => ["sqlite", "sqlite3", "db"].contains(&ext.to_lowercase().as_str()),
+        None => false
+    };
+}
+
+/// Selects the backend by the file extension: .sqlite, .sqlite3 and .db use SQLite, everything else JSON
+pub fn open_storage(file_name : &str) -> Result<Box<dyn Storage>, Box<dyn Error>>
+{
+    if is_sqlite_file(file_name)
+    {
+        #[cfg(feature = "sqlite")]
+        return Ok(Box::new(crate::sqlite::SqliteStorage::open(file_name)?));
+
+        #[cfg(not(feature = "sqlite"))]
+        return Err(Box::new(GenericError::new(format!("{} is a SQLite database, but invi was built without the sqlite feature", file_name))));
+    }
+
+    return Ok(Box::new(JsonStorage::new(file_name)));
+}
+
+/// Like open_storage, but a database which does not exist yet is created. Opening never creates
+/// one, so a mistyped file name or a backup which is only read stay as they are.
+pub fn create_storage(file_name : &str) -> Result<Box<dyn Storage>, Box<dyn Error>>
+{
+    #[cfg(feature = "sqlite")]
+    if is_sqlite_file(file_name) {return Ok(Box::new(crate::sqlite::SqliteStorage::create(file_name)?));}
+
+    return open_storage(file_name);
+}
+
+/// Creates a new blank inventory in the backend matching the file extension
+pub fn create_inventory(file_name : &str) -> Result<Box<dyn Storage>, Box<dyn Error>>
+{
+    let mut storage = create_storage(file_name)?;
+    storage.save(&Inventory::new())?;
+    return Ok(storage);
+}
+
+pub fn load_file(file_name : &str) -> Result<Inventory, Box<dyn Error>>
+{
+    return open_storage(file_name)?.load();
+}
+
+/// Creates the backup after a save, the data was already written when this fails
+pub fn backup_after_save(file_name : &str) -> Result<(), Box<dyn Error>>
+{
+    if let Err(e) = backup::create_backup(file_name)
+    {
+        return Err(Box::new(GenericError::new(format!("The inventory was saved, but the backup failed: {}", e))));
+    }
+    return Ok(());
+}